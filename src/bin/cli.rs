@@ -0,0 +1,320 @@
+extern crate env_logger;
+#[macro_use]
+extern crate log;
+
+extern crate actix_web;
+#[macro_use]
+extern crate structopt;
+extern crate chrono;
+extern crate failure;
+extern crate futures;
+extern crate serde_json;
+
+extern crate beast_glatisant;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+use actix_web::actix;
+use beast_glatisant::check::{self, CodeAndResults, Results};
+use beast_glatisant::playground::{Channel, Edition, Mode, Options};
+use beast_glatisant::settings::Settings;
+use chrono::{offset::Utc, Duration};
+use futures::future::{self, Future};
+use structopt::StructOpt;
+
+#[derive(Debug)]
+struct RepoReference {
+    owner: String,
+    repo: String,
+}
+impl FromStr for RepoReference {
+    type Err = String;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        let mut parts = reference.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(repo)) if !owner.is_empty() && !repo.is_empty() => {
+                Ok(RepoReference {
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                })
+            }
+            _ => Err(format!(
+                "invalid repository {}, expected owner/repo",
+                reference
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IssueReference {
+    owner: String,
+    repo: String,
+    issue: u32,
+}
+impl FromStr for IssueReference {
+    type Err = String;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        let mut parts = reference.splitn(2, '#');
+        let repo = parts.next().unwrap_or("").parse::<RepoReference>()?;
+        let issue = parts
+            .next()
+            .and_then(|issue| issue.parse().ok())
+            .ok_or_else(|| format!("invalid issue {}, expected owner/repo#number", reference))?;
+        Ok(IssueReference {
+            owner: repo.owner,
+            repo: repo.repo,
+            issue,
+        })
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Check code samples from an issue and its comments
+    #[structopt(name = "issue")]
+    Issue {
+        /// Issue to check, as owner/repo#number
+        issue: IssueReference,
+        /// Comment the results on the issue, requires a Github token
+        #[structopt(long = "comment")]
+        comment: bool,
+    },
+    /// Check code samples from a pull request, its comments and its reviews
+    #[structopt(name = "pull")]
+    Pull {
+        /// Pull request to check, as owner/repo#number
+        pull: IssueReference,
+        /// Comment the results on the pull request, requires a Github token
+        #[structopt(long = "comment")]
+        comment: bool,
+    },
+    /// Check code samples from issues and comments of a repository updated recently
+    #[structopt(name = "latest")]
+    Latest {
+        /// Repository to check, as owner/repo
+        repo: RepoReference,
+        /// Number of days to look back
+        #[structopt(long = "days", short = "d", default_value = "2")]
+        days: i64,
+    },
+    /// Check the files of a gist
+    #[structopt(name = "gist")]
+    Gist {
+        /// Id of the gist
+        id: String,
+    },
+    /// Check code samples from a local markdown file
+    #[structopt(name = "file")]
+    File {
+        /// Path to the markdown file
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "beast_glatisant_cli", author = "")]
+struct Config {
+    /// Action to run on code samples: run, test, format, clippy, all, matrix or verdict
+    #[structopt(long = "action", short = "a", default_value = "clippy")]
+    pub action: check::Action,
+    /// Channel to use: stable, beta or nightly
+    #[structopt(long = "channel", short = "c", default_value = "stable")]
+    pub channel: Channel,
+    /// Mode to use: debug or release
+    #[structopt(long = "mode", short = "m", default_value = "debug")]
+    pub mode: Mode,
+    /// Edition to use: 2015, 2018, 2021 or 2024
+    #[structopt(long = "edition", short = "e")]
+    pub edition: Option<Edition>,
+    /// Print results as JSON
+    #[structopt(long = "json")]
+    pub json: bool,
+    /// Github token
+    #[structopt(long = "token", short = "t", env = "GITHUB_TOKEN")]
+    pub token: Option<String>,
+    #[structopt(flatten)]
+    pub settings: Settings,
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+fn run(
+    command: Command,
+    action: check::Action,
+    options: Options,
+    token: Option<String>,
+) -> Box<Future<Item = Vec<CodeAndResults>, Error = failure::Error>> {
+    match command {
+        Command::Issue { issue, comment } => {
+            let results = check::check_issue(
+                &issue.owner,
+                &issue.repo,
+                issue.issue,
+                action,
+                options,
+                token.clone(),
+            );
+            comment_if_asked(issue, results, comment, options, token)
+        }
+        Command::Pull { pull, comment } => {
+            let results = check::check_pull(
+                &pull.owner,
+                &pull.repo,
+                pull.issue,
+                action,
+                options,
+                token.clone(),
+            );
+            comment_if_asked(pull, results, comment, options, token)
+        }
+        Command::Latest { repo, days } => match token {
+            Some(token) => Box::new(check::check_latest(
+                &repo.owner,
+                &repo.repo,
+                (Utc::now() - Duration::days(days)).timestamp(),
+                action,
+                options,
+                &token,
+            )),
+            None => Box::new(future::err(failure::err_msg(
+                "a Github token is required to list latest issues",
+            ))),
+        },
+        Command::Gist { id } => Box::new(
+            beast_glatisant::github::gist::get_gist(&id, token).and_then(move |gist| {
+                let from = gist.html_url.clone();
+                let code = beast_glatisant::markdown::gist_sample(gist, Default::default());
+                future::result(code).and_then(move |code| {
+                    check::check_samples(from, None, vec![code], action, options)
+                })
+            }),
+        ),
+        Command::File { path } => match fs::read_to_string(&path) {
+            Ok(text) => check::check_markdown(
+                path.display().to_string(),
+                None,
+                &text,
+                action,
+                options,
+                &token,
+            ),
+            Err(err) => Box::new(future::err(err.into())),
+        },
+    }
+}
+
+/// Comment the results on the issue or pull request when asked, keeping them as the output
+fn comment_if_asked<F>(
+    issue: IssueReference,
+    results: F,
+    comment: bool,
+    options: Options,
+    token: Option<String>,
+) -> Box<Future<Item = Vec<CodeAndResults>, Error = failure::Error>>
+where
+    F: Future<Item = Vec<CodeAndResults>, Error = failure::Error> + 'static,
+{
+    match (comment, token) {
+        (false, _) => Box::new(results),
+        (true, Some(token)) => Box::new(results.and_then(move |code_blocks| {
+            beast_glatisant::github::issue::upsert_bot_comment(
+                &issue.owner,
+                &issue.repo,
+                issue.issue,
+                &check::render_comment(&code_blocks, options),
+                token,
+            ).map(move |comment| {
+                info!("results commented on {}", comment.html_url);
+                code_blocks
+            })
+        })),
+        (true, None) => Box::new(future::err(failure::err_msg(
+            "a Github token is required to comment",
+        ))),
+    }
+}
+
+fn print_results(code_blocks: &[CodeAndResults]) {
+    for code_block in code_blocks {
+        let results = match code_block.results {
+            Some(ref results) => results,
+            None => continue,
+        };
+        println!(
+            "From {}\n\n{}\n",
+            code_block.from,
+            code_block.code.trim_right()
+        );
+        for file in &code_block.files {
+            println!("--- {} ---\n{}\n", file.name, file.content.trim_right());
+        }
+        match results {
+            Results::Actions(results) => {
+                for result in results {
+                    println!(
+                        "--- {} ---\n{}\n",
+                        result.action.name(),
+                        result.output.trim_right()
+                    );
+                }
+            }
+            Results::Matrix(matrix) => {
+                println!("--- {} ---\n", matrix.summary);
+                for result in &matrix.results {
+                    println!(
+                        "--- {} ({}) ---\n{}\n",
+                        result.channel.name(),
+                        if result.success { "success" } else { "failure" },
+                        result.output.trim_right()
+                    );
+                }
+            }
+            Results::Verdict(verdict) => {
+                println!("--- {} ---\n", verdict.verdict.name());
+                if !verdict.diff.is_empty() {
+                    println!("{}", verdict.diff);
+                }
+                println!("{}\n", verdict.output.trim_right());
+            }
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let config = Config::from_args();
+
+    let settings = &config.settings;
+    if let Err(err) = settings.apply().and_then(|_| settings.set_github_cache()) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+
+    let options = Options {
+        channel: config.channel,
+        mode: config.mode,
+        edition: config.edition,
+    };
+    let action = config.action;
+    let token = config.token;
+    let command = config.command;
+    let mut system = actix::System::new("beast_glatisant_cli");
+    match system.block_on(future::lazy(move || run(command, action, options, token))) {
+        Ok(ref code_blocks) if config.json => {
+            println!("{}", serde_json::to_string_pretty(code_blocks).unwrap())
+        }
+        Ok(ref code_blocks) => print_results(code_blocks),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -10,16 +10,18 @@ extern crate serde_derive;
 extern crate chrono;
 extern crate failure;
 extern crate futures;
+extern crate serde_json;
 
 use chrono::{offset::Utc, Duration};
 
 extern crate beast_glatisant;
 
-use std::iter;
-
 use actix_web::{
     fs, http, middleware, server, App, HttpMessage, HttpRequest, HttpResponse, Path, Query,
 };
+use beast_glatisant::check;
+use beast_glatisant::github::cache;
+use beast_glatisant::settings::Settings;
 use futures::future::{self, Future};
 use structopt::StructOpt;
 
@@ -35,35 +37,43 @@ impl FromDays {
 }
 
 #[derive(Deserialize, Debug, Hash, Eq, PartialEq)]
-#[serde(rename_all = "camelCase")]
-enum Action {
-    Clippy,
+struct IssueDesignation {
+    owner: String,
+    repo: String,
+    issue: u32,
+    action: check::Action,
 }
 
 #[derive(Deserialize, Debug, Hash, Eq, PartialEq)]
-struct IssueDesignation {
+struct IssueNumber {
     owner: String,
     repo: String,
     issue: u32,
-    action: Action,
+}
+
+#[derive(Deserialize, Debug, Hash, Eq, PartialEq)]
+struct PullDesignation {
+    owner: String,
+    repo: String,
+    pull: u32,
+    action: check::Action,
 }
 
 #[derive(Deserialize, Debug, Hash, Eq, PartialEq)]
 struct RepoDesignation {
     owner: String,
     repo: String,
-    action: Action,
+    action: check::Action,
 }
 
-#[derive(Serialize, Debug, Clone)]
-struct CodeAndClippy {
-    from: String,
-    code: String,
-    clippy: Option<String>,
-    ts: Option<chrono::DateTime<Utc>>,
+#[derive(Debug, Clone)]
+struct AppState {
+    webhook_secret: Option<String>,
+    webhook_action: check::Action,
+    github_token: Option<String>,
 }
 
-fn extract_token(req: HttpRequest) -> Option<String> {
+fn extract_token(req: HttpRequest<AppState>) -> Option<String> {
     req.headers()
         .get(http::header::AUTHORIZATION)
         .and_then(|value| {
@@ -81,115 +91,284 @@ fn extract_token(req: HttpRequest) -> Option<String> {
 }
 
 fn get_issue(
-    info: (Path<IssueDesignation>, HttpRequest),
+    info: (
+        Path<IssueDesignation>,
+        Query<beast_glatisant::playground::Options>,
+        HttpRequest<AppState>,
+    ),
 ) -> impl Future<Item = HttpResponse, Error = failure::Error> {
-    let token = extract_token(info.1.clone());
-    let token2 = token.clone();
-    beast_glatisant::github::issue::get_issue(
+    let token = extract_token(info.2.clone());
+    check::check_issue(
         &info.0.owner,
         &info.0.repo,
         info.0.issue,
-        token.clone(),
-    ).and_then(move |issue| {
-        beast_glatisant::github::issue::get_comments(
-            &info.0.owner,
-            &info.0.repo,
-            info.0.issue,
-            token.clone(),
-        ).map(move |comments| {
-            iter::once((issue.html_url, issue.body))
-                .chain(
-                    comments
-                        .iter()
-                        .map(|comment| (comment.html_url.clone(), comment.body.clone())),
-                ).collect()
-        })
-    }).and_then(move |issue_and_comments: Vec<(String, String)>| {
-        future::join_all(
-            issue_and_comments
-                .iter()
-                .map(move |(from, text)| {
-                    let from = from.clone();
-                    beast_glatisant::markdown::get_code_samples(&text.clone(), &token2).map(
-                        move |code_blocks| {
-                            code_blocks
-                                .iter()
-                                .map(|code_block| (from.clone(), code_block.clone()))
-                                .collect::<Vec<(String, beast_glatisant::markdown::Code)>>()
-                        },
-                    )
-                }).collect::<Vec<_>>(),
-        )
-    }).and_then(|code_blocks| {
-        future::join_all(
-            code_blocks
-                .iter()
-                .flat_map(|cbs| cbs)
-                .map(move |(from, cb)| {
-                    let cb = cb.clone();
-                    let from = from.clone();
-                    clippy_if_rust(&cb).map(|clippy| CodeAndClippy {
-                        from: from,
-                        code: cb.code,
-                        clippy: clippy,
-                        ts: None,
-                    })
-                }).collect::<Vec<_>>(),
-        )
-    }).map(|code_blocks| HttpResponse::Ok().json(code_blocks))
+        info.0.action,
+        *info.1,
+        token,
+    ).map(|code_blocks| HttpResponse::Ok().json(code_blocks))
+}
+
+fn comment_issue(
+    info: (
+        Path<IssueDesignation>,
+        Query<beast_glatisant::playground::Options>,
+        HttpRequest<AppState>,
+    ),
+) -> Box<Future<Item = HttpResponse, Error = failure::Error>> {
+    let token = match extract_token(info.2.clone()) {
+        Some(token) => token,
+        None => return Box::new(future::ok(HttpResponse::Unauthorized().finish())),
+    };
+    Box::new(comment_results(info.0.into_inner(), *info.1, token))
+}
+
+fn comment_results(
+    designation: IssueDesignation,
+    options: beast_glatisant::playground::Options,
+    token: String,
+) -> impl Future<Item = HttpResponse, Error = failure::Error> {
+    let results = check::check_issue(
+        &designation.owner,
+        &designation.repo,
+        designation.issue,
+        designation.action,
+        options,
+        Some(token.clone()),
+    );
+    post_results(
+        designation.owner,
+        designation.repo,
+        designation.issue,
+        results,
+        options,
+        token,
+    )
+}
+
+/// Comment results on an issue or a pull request, replacing the previous results
+fn post_results<F>(
+    owner: String,
+    repo: String,
+    number: u32,
+    results: F,
+    options: beast_glatisant::playground::Options,
+    token: String,
+) -> impl Future<Item = HttpResponse, Error = failure::Error>
+where
+    F: Future<Item = Vec<check::CodeAndResults>, Error = failure::Error>,
+{
+    results
+        .and_then(move |code_blocks| {
+            beast_glatisant::github::issue::upsert_bot_comment(
+                &owner,
+                &repo,
+                number,
+                &check::render_comment(&code_blocks, options),
+                token,
+            )
+        }).map(|comment| HttpResponse::Ok().json(comment))
+}
+
+/// Every recorded run of the samples of an issue and its comments
+fn issue_history(
+    issue: Path<IssueNumber>,
+) -> Box<Future<Item = HttpResponse, Error = failure::Error>> {
+    let store = match beast_glatisant::store::store() {
+        Some(store) => store,
+        None => {
+            return Box::new(future::ok(
+                HttpResponse::NotFound().body("results are not recorded"),
+            ))
+        }
+    };
+    let history = beast_glatisant::store::History {
+        from: format!(
+            "https://github.com/{}/{}/issues/{}",
+            issue.owner, issue.repo, issue.issue
+        ),
+    };
+    Box::new(
+        store
+            .send(history)
+            .map_err(failure::Error::from)
+            .and_then(|histories| histories)
+            .map(|histories| HttpResponse::Ok().json(histories)),
+    )
+}
+
+fn get_pull(
+    info: (
+        Path<PullDesignation>,
+        Query<beast_glatisant::playground::Options>,
+        HttpRequest<AppState>,
+    ),
+) -> impl Future<Item = HttpResponse, Error = failure::Error> {
+    let token = extract_token(info.2.clone());
+    check::check_pull(
+        &info.0.owner,
+        &info.0.repo,
+        info.0.pull,
+        info.0.action,
+        *info.1,
+        token,
+    ).map(|code_blocks| HttpResponse::Ok().json(code_blocks))
+}
+
+fn comment_pull(
+    info: (
+        Path<PullDesignation>,
+        Query<beast_glatisant::playground::Options>,
+        HttpRequest<AppState>,
+    ),
+) -> Box<Future<Item = HttpResponse, Error = failure::Error>> {
+    let token = match extract_token(info.2.clone()) {
+        Some(token) => token,
+        None => return Box::new(future::ok(HttpResponse::Unauthorized().finish())),
+    };
+    let designation = info.0.into_inner();
+    let results = check::check_pull(
+        &designation.owner,
+        &designation.repo,
+        designation.pull,
+        designation.action,
+        *info.1,
+        Some(token.clone()),
+    );
+    Box::new(post_results(
+        designation.owner,
+        designation.repo,
+        designation.pull,
+        results,
+        *info.1,
+        token,
+    ))
 }
 
 fn repo_issues(
-    info: (Path<RepoDesignation>, Query<FromDays>, HttpRequest),
+    info: (
+        Path<RepoDesignation>,
+        Query<FromDays>,
+        Query<beast_glatisant::playground::Options>,
+        HttpRequest<AppState>,
+    ),
 ) -> impl Future<Item = HttpResponse, Error = failure::Error> {
-    let token = extract_token(info.2);
-    let token2 = token.clone();
-    let from_ts = info.1.timestamp();
-    beast_glatisant::github::graphql_issue_list::graphql(
+    let token = extract_token(info.3);
+    check::check_latest(
         &info.0.owner,
         &info.0.repo,
-        &token.unwrap().clone(),
-    ).map(|response| response.list())
-    .and_then(move |issue_and_comments| {
-        future::join_all(
-            issue_and_comments
-                .iter()
-                .filter(move |comm| comm.last_update.timestamp() > from_ts)
-                .map(|comm| {
-                    let url = comm.url.clone();
-                    let update = comm.last_update.clone();
-                    beast_glatisant::markdown::get_code_samples(&comm.body.clone(), &token2).map(
-                        move |code_blocks| {
-                            code_blocks
-                                .iter()
-                                .map(|code_block| (url.clone(), update, code_block.clone()))
-                                .collect::<Vec<(
-                                    String,
-                                    chrono::DateTime<Utc>,
-                                    beast_glatisant::markdown::Code,
-                                )>>()
-                        },
-                    )
-                }).collect::<Vec<_>>(),
-        )
-    }).and_then(|code_blocks| {
-        future::join_all(
-            code_blocks
-                .iter()
-                .flat_map(|cbs| cbs)
-                .map(move |(from, updated_at, cb)| {
-                    let cb = cb.clone();
-                    let from = from.clone();
-                    let updated_at = updated_at.clone();
-                    clippy_if_rust(&cb).map(move |clippy| CodeAndClippy {
-                        from: from,
-                        code: cb.code,
-                        clippy: clippy,
-                        ts: Some(updated_at),
-                    })
-                }).collect::<Vec<_>>(),
-        )
-    }).map(|code_blocks| HttpResponse::Ok().json(code_blocks))
+        info.1.timestamp(),
+        info.0.action,
+        *info.2,
+        &token.unwrap(),
+    ).map(|code_blocks| HttpResponse::Ok().json(code_blocks))
+}
+
+fn cache_stats(_req: &HttpRequest<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(cache::stats())
+}
+
+const WEBHOOK_PAYLOAD_LIMIT: usize = 5_242_880;
+
+fn webhook(req: HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error = failure::Error>> {
+    let state = req.state().clone();
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let event = header("X-GitHub-Event").unwrap_or_default();
+    let signature = header("X-Hub-Signature");
+    Box::new(
+        req.body()
+            .limit(WEBHOOK_PAYLOAD_LIMIT)
+            .map_err(|err| err.into())
+            .and_then(
+                move |body| -> Box<Future<Item = HttpResponse, Error = failure::Error>> {
+                    let verified = match (&state.webhook_secret, &signature) {
+                        (Some(secret), Some(signature)) => {
+                            beast_glatisant::github::webhook::verify_signature(
+                                secret, signature, &body,
+                            )
+                        }
+                        _ => false,
+                    };
+                    if !verified {
+                        warn!("rejected webhook call with invalid signature");
+                        return Box::new(future::ok(HttpResponse::Unauthorized().finish()));
+                    }
+                    // `ping` when the hook is created, and any other event it is subscribed to
+                    if event != "issues" && event != "issue_comment" {
+                        debug!("ignored {} webhook event", event);
+                        return Box::new(future::ok(HttpResponse::Ok().finish()));
+                    }
+                    let payload: beast_glatisant::github::webhook::Payload =
+                        match serde_json::from_slice(&body) {
+                            Ok(payload) => payload,
+                            Err(err) => {
+                                warn!("could not read {} webhook payload: {}", event, err);
+                                return Box::new(future::ok(HttpResponse::BadRequest().finish()));
+                            }
+                        };
+                    if payload.updated_body(&event).is_none() {
+                        return Box::new(future::ok(HttpResponse::Ok().finish()));
+                    }
+                    // Github gives up on webhooks after a few seconds, the samples are checked
+                    // after answering
+                    actix_web::actix::spawn(
+                        check_webhook_issue(state, &payload)
+                            .map_err(|err| warn!("could not check webhook issue: {}", err)),
+                    );
+                    Box::new(future::ok(HttpResponse::Accepted().finish()))
+                },
+            ),
+    )
+}
+
+/// Check the samples of the issue whose description or comment was created or edited by a
+/// webhook event, and comment the results on the issue if there is a token. The whole issue is
+/// checked again, as the bot comment holds the results of all its samples.
+fn check_webhook_issue(
+    state: AppState,
+    payload: &beast_glatisant::github::webhook::Payload,
+) -> impl Future<Item = (), Error = failure::Error> {
+    let owner = payload.repository.owner.login.clone();
+    let repo = payload.repository.name.clone();
+    let number = payload.issue.number;
+    let options = beast_glatisant::playground::Options::default();
+    check::check_issue(
+        &owner,
+        &repo,
+        number,
+        state.webhook_action,
+        options,
+        state.github_token.clone(),
+    ).and_then(
+        move |code_blocks| -> Box<Future<Item = (), Error = failure::Error>> {
+            match state.github_token {
+                Some(ref token) if !code_blocks.is_empty() => Box::new(
+                    post_results(
+                        owner,
+                        repo,
+                        number,
+                        future::ok(code_blocks),
+                        options,
+                        token.clone(),
+                    ).map(|_| ()),
+                ),
+                _ => {
+                    info!(
+                        "checked {} code samples from {}/{}#{}",
+                        code_blocks.len(),
+                        owner,
+                        repo,
+                        number
+                    );
+                    Box::new(future::ok(()))
+                }
+            }
+        },
+    )
 }
 
 #[derive(StructOpt, Debug)]
@@ -206,6 +385,21 @@ struct Config {
         default_value = "7878"
     )]
     pub port: u16,
+    /// Secret used to sign Github webhook payloads, webhooks are rejected if not set
+    #[structopt(long = "webhook-secret", env = "WEBHOOK_SECRET")]
+    pub webhook_secret: Option<String>,
+    /// Action to run on code samples received through the webhook
+    #[structopt(long = "webhook-action", default_value = "clippy")]
+    pub webhook_action: check::Action,
+    /// Github token used to fetch issues and comment results from the webhook
+    #[structopt(long = "github-token", env = "GITHUB_TOKEN")]
+    pub github_token: Option<String>,
+    /// Acknowledge that code samples run with `--local` are not sandboxed, and that the server is
+    /// isolated in a container or a virtual machine
+    #[structopt(long = "unsandboxed")]
+    pub unsandboxed: bool,
+    #[structopt(flatten)]
+    pub settings: Settings,
 }
 
 fn main() {
@@ -213,15 +407,48 @@ fn main() {
 
     let config = Config::from_args();
 
+    let settings = &config.settings;
+    if settings.local && !config.unsandboxed {
+        eprintln!(
+            "error: --local runs code from Github issues with the rights of this server, \
+             run it in a container or a virtual machine and add --unsandboxed"
+        );
+        std::process::exit(1);
+    }
+    if let Err(err) = settings.apply() {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+    if let Err(err) = settings.set_github_cache() {
+        // the server works without it, answers are only fetched again after a restart
+        error!("{}, keeping answers in memory", err);
+        cache::set_cache(cache::Memory::new(settings.github_cache_bounds()));
+    }
+
     let addr = format!("{}:{}", config.host, config.port);
+    let state = AppState {
+        webhook_secret: config.webhook_secret,
+        webhook_action: config.webhook_action,
+        github_token: config.github_token,
+    };
     info!("listening on http://{}", addr);
-    server::new(|| {
-        App::new()
+    server::new(move || {
+        App::with_state(state.clone())
             .middleware(middleware::Logger::default())
-            .resource("/{owner}/{repo}/issues/latest/{action}", |r| {
+            .resource("/webhook", |r| {
+                r.method(http::Method::POST).with_async(webhook)
+            }).resource("/cache/stats", |r| {
+                r.method(http::Method::GET).f(cache_stats)
+            }).resource("/{owner}/{repo}/issues/latest/{action}", |r| {
                 r.method(http::Method::GET).with_async(repo_issues)
+            }).resource("/{owner}/{repo}/issues/{issue}/history", |r| {
+                r.method(http::Method::GET).with_async(issue_history)
             }).resource("/{owner}/{repo}/issues/{issue}/{action}", |r| {
-                r.method(http::Method::GET).with_async(get_issue)
+                r.method(http::Method::GET).with_async(get_issue);
+                r.method(http::Method::POST).with_async(comment_issue)
+            }).resource("/{owner}/{repo}/pulls/{pull}/{action}", |r| {
+                r.method(http::Method::GET).with_async(get_pull);
+                r.method(http::Method::POST).with_async(comment_pull)
             }).handler(
                 "/",
                 fs::StaticFiles::new("./static/")
@@ -232,26 +459,3 @@ fn main() {
     .unwrap()
     .run();
 }
-
-fn is_rust(code: &beast_glatisant::markdown::Code) -> bool {
-    if let Some(ref language) = code.language {
-        language.to_ascii_lowercase() == "rust"
-    } else {
-        false
-    }
-}
-
-fn clippy_if_rust(
-    code: &beast_glatisant::markdown::Code,
-) -> Box<Future<Item = Option<String>, Error = failure::Error>> {
-    if is_rust(code) {
-        Box::new(
-            beast_glatisant::playground::ask_playground_simpl(
-                &code.code,
-                beast_glatisant::playground::Action::Clippy,
-            ).map(|v| Some(v)),
-        )
-    } else {
-        Box::new(future::ok(None))
-    }
-}
@@ -0,0 +1,591 @@
+use std::iter;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use failure;
+use futures::future::{self, Future};
+
+use github;
+use markdown::{self, Code};
+use playground::diagnostics::Diagnostic;
+use playground::{self, Options};
+use store;
+use verdict::{self, VerdictResult};
+
+#[derive(Deserialize, Debug, Hash, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    Run,
+    Test,
+    Format,
+    Clippy,
+    All,
+    Matrix,
+    Verdict,
+}
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "run" => Ok(Action::Run),
+            "test" => Ok(Action::Test),
+            "format" => Ok(Action::Format),
+            "clippy" => Ok(Action::Clippy),
+            "all" => Ok(Action::All),
+            "matrix" => Ok(Action::Matrix),
+            "verdict" => Ok(Action::Verdict),
+            _ => Err(format!("unknown action {}", action)),
+        }
+    }
+}
+impl Action {
+    pub fn playground_actions(self) -> Vec<playground::Action> {
+        match self {
+            Action::Run => vec![playground::Action::Run],
+            Action::Test => vec![playground::Action::Test],
+            Action::Format => vec![playground::Action::Format],
+            Action::Clippy => vec![playground::Action::Clippy],
+            Action::All => vec![
+                playground::Action::Run,
+                playground::Action::Test,
+                playground::Action::Format,
+                playground::Action::Clippy,
+            ],
+            Action::Matrix | Action::Verdict => vec![playground::Action::Run],
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ActionResult {
+    pub action: playground::Action,
+    /// Whether the sample did what its fence attributes say it should
+    pub success: bool,
+    pub output: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Results {
+    Actions(Vec<ActionResult>),
+    Matrix(playground::ChannelMatrix),
+    Verdict(VerdictResult),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CodeAndResults {
+    pub from: String,
+    pub code: String,
+    /// Other files of the sample, for gists with several files
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<playground::SourceFile>,
+    pub results: Option<Results>,
+    pub ts: Option<DateTime<Utc>>,
+}
+
+pub fn run_if_rust(
+    code: &Code,
+    action: Action,
+    options: Options,
+) -> Box<Future<Item = Option<Results>, Error = failure::Error>> {
+    if !code.is_rust() || code.attributes.ignore {
+        return Box::new(future::ok(None));
+    }
+    let options = sample_options(code, options);
+    match action {
+        Action::Matrix => match code.attributes.action(playground::Action::Run) {
+            Some(action) => Box::new(
+                playground::ask_channel_matrix(code, action, options)
+                    .map(|matrix| Some(Results::Matrix(matrix))),
+            ),
+            None => Box::new(future::ok(None)),
+        },
+        Action::Verdict => match verdict::ask_verdict(code, options) {
+            Some(verdict) => Box::new(verdict.map(|verdict| Some(Results::Verdict(verdict)))),
+            None => Box::new(future::ok(None)),
+        },
+        _ => {
+            let attributes = code.attributes;
+            Box::new(
+                future::join_all(
+                    action
+                        .playground_actions()
+                        .into_iter()
+                        .filter_map(|action| attributes.action(action))
+                        .map(|action| {
+                            let project = playground::Project::from_code(code);
+                            playground::ask_sample(code, action, options).map(move |response| {
+                                ActionResult {
+                                    action,
+                                    success: attributes.is_success(action, &response),
+                                    diagnostics: playground::sample_diagnostics(
+                                        &project,
+                                        &response.stderr,
+                                    ),
+                                    output: playground::simplify(action, response),
+                                }
+                            })
+                        }).collect::<Vec<_>>(),
+                ).map(|results| Some(Results::Actions(results))),
+            )
+        }
+    }
+}
+
+/// Options given in the sample override the requested ones
+fn sample_options(code: &Code, options: Options) -> Options {
+    let mut options = code.options.apply(options);
+    if code.attributes.edition.is_some() {
+        options.edition = code.attributes.edition;
+    }
+    options
+}
+
+/// Keep the results in the store if there is one, with the version of the compilers used
+fn record(
+    from: &str,
+    code: &Code,
+    options: Options,
+    results: &Option<Results>,
+) -> Box<Future<Item = (), Error = failure::Error>> {
+    let (store, results) = match (store::store(), results) {
+        (Some(store), Some(results)) => (store, results),
+        _ => return Box::new(future::ok(())),
+    };
+    let options = sample_options(code, options);
+    let ts = Utc::now();
+    let run = |action: playground::Action,
+               channel: playground::Channel,
+               success: bool,
+               output: &str,
+               verdict: Option<&str>| store::Run {
+        action: action.name().to_string(),
+        channel,
+        version: None,
+        success,
+        output: output.to_string(),
+        verdict: verdict.map(|verdict| verdict.to_string()),
+        ts,
+        changed: false,
+    };
+    // the action asked for the run, unless the fence attributes ask for another one
+    let action = code
+        .attributes
+        .action(playground::Action::Run)
+        .unwrap_or(playground::Action::Run);
+    let runs = match results {
+        Results::Actions(results) => results
+            .iter()
+            .map(|result| {
+                run(
+                    result.action,
+                    options.channel,
+                    result.success,
+                    &result.output,
+                    None,
+                )
+            }).collect::<Vec<_>>(),
+        Results::Matrix(matrix) => matrix
+            .results
+            .iter()
+            .map(|result| run(action, result.channel, result.success, &result.output, None))
+            .collect(),
+        Results::Verdict(verdict) => vec![run(
+            action,
+            options.channel,
+            verdict.success,
+            &verdict.output,
+            Some(verdict.verdict.name()),
+        )],
+    };
+    let from = from.to_string();
+    let code_text = code.code.clone();
+    let files = code.files.clone();
+    Box::new(
+        future::join_all(
+            runs.into_iter()
+                .map(|mut run| {
+                    // a version that cannot be known does not prevent recording the run
+                    playground::ask_version(run.channel).then(move |version| {
+                        run.version = version.ok().map(|version| version.name());
+                        Ok(run)
+                    })
+                }).collect::<Vec<_>>(),
+        ).and_then(move |runs| {
+            let record = store::Record {
+                from: from.clone(),
+                code: code_text,
+                files,
+                runs,
+            };
+            store
+                .send(record)
+                .map_err(failure::Error::from)
+                .and_then(|recorded| recorded)
+                .or_else(move |err| {
+                    warn!("could not record results from {}: {}", from, err);
+                    Ok(())
+                })
+        }),
+    )
+}
+
+pub fn check_samples(
+    from: String,
+    ts: Option<DateTime<Utc>>,
+    code_blocks: Vec<Code>,
+    action: Action,
+    options: Options,
+) -> impl Future<Item = Vec<CodeAndResults>, Error = failure::Error> {
+    future::join_all(
+        code_blocks
+            .into_iter()
+            .map(move |cb| {
+                let from = from.clone();
+                run_if_rust(&cb, action, options).and_then(move |results| {
+                    let recorded = record(&from, &cb, options, &results);
+                    recorded.map(move |()| CodeAndResults {
+                        from: from,
+                        code: cb.code,
+                        files: cb.files,
+                        results: results,
+                        ts: ts,
+                    })
+                })
+            }).collect::<Vec<_>>(),
+    )
+}
+
+pub fn check_markdown(
+    from: String,
+    ts: Option<DateTime<Utc>>,
+    text: &str,
+    action: Action,
+    options: Options,
+    token: &Option<String>,
+) -> Box<Future<Item = Vec<CodeAndResults>, Error = failure::Error>> {
+    Box::new(
+        markdown::get_code_samples(text, token)
+            .and_then(move |code_blocks| check_samples(from, ts, code_blocks, action, options)),
+    )
+}
+
+pub fn check_issue(
+    owner: &str,
+    repo: &str,
+    issue_number: u32,
+    action: Action,
+    options: Options,
+    token: Option<String>,
+) -> impl Future<Item = Vec<CodeAndResults>, Error = failure::Error> {
+    let owner = owner.to_string();
+    let repo = repo.to_string();
+    let token2 = token.clone();
+    github::issue::get_issue(&owner, &repo, issue_number, token.clone())
+        .and_then(move |issue| {
+            github::issue::get_comments(&owner, &repo, issue_number, token)
+                .map(move |comments| issue_texts(issue, comments))
+        }).and_then(move |issue_and_comments| {
+            future::join_all(
+                issue_and_comments
+                    .into_iter()
+                    .map(|(from, text)| check_markdown(from, None, &text, action, options, &token2))
+                    .collect::<Vec<_>>(),
+            )
+        }).map(flatten)
+}
+
+/// Where the description and each comment of an issue come from, with their text. The comment of
+/// the bot is left out, its samples are copies of the ones it checked.
+fn issue_texts(
+    issue: github::issue::Issue,
+    comments: Vec<github::issue::Comment>,
+) -> Vec<(String, String)> {
+    iter::once((issue.html_url, issue.body.unwrap_or_default()))
+        .chain(
+            comments
+                .into_iter()
+                .filter(|comment| !comment.body.starts_with(github::issue::BOT_COMMENT_MARKER))
+                .map(|comment| (comment.html_url, comment.body)),
+        ).collect()
+}
+
+/// Check the description of a pull request, its conversation, its reviews and the comments on
+/// its diff
+pub fn check_pull(
+    owner: &str,
+    repo: &str,
+    pull_number: u32,
+    action: Action,
+    options: Options,
+    token: Option<String>,
+) -> impl Future<Item = Vec<CodeAndResults>, Error = failure::Error> {
+    github::pull::get_pull(owner, repo, pull_number, token.clone())
+        .join4(
+            github::issue::get_comments(owner, repo, pull_number, token.clone()),
+            github::pull::get_reviews(owner, repo, pull_number, token.clone()),
+            github::pull::get_review_comments(owner, repo, pull_number, token.clone()),
+        ).and_then(move |(pull, comments, reviews, review_comments)| {
+            let texts = iter::once((pull.html_url, pull.body.unwrap_or_default()))
+                .chain(
+                    comments
+                        .into_iter()
+                        .filter(|comment| {
+                            !comment.body.starts_with(github::issue::BOT_COMMENT_MARKER)
+                        }).map(|comment| (comment.html_url, comment.body)),
+                ).chain(reviews.into_iter().filter_map(|review| {
+                    let html_url = review.html_url;
+                    review.body.map(|body| (html_url, body))
+                }));
+            future::join_all(
+                texts
+                    .map(|(from, text)| check_markdown(from, None, &text, action, options, &token))
+                    .chain(
+                        review_comments
+                            .into_iter()
+                            .map(|comment| check_review_comment(comment, action, options, &token)),
+                    ).collect::<Vec<_>>(),
+            )
+        }).map(flatten)
+}
+
+/// Changes suggested in a review are in `suggestion` blocks, which hold Rust when commenting on a
+/// Rust file
+fn check_review_comment(
+    comment: github::pull::ReviewComment,
+    action: Action,
+    options: Options,
+    token: &Option<String>,
+) -> Box<Future<Item = Vec<CodeAndResults>, Error = failure::Error>> {
+    let is_rust_file = comment.path.ends_with(".rs");
+    let from = comment.html_url;
+    Box::new(
+        markdown::get_code_samples(&comment.body, token).and_then(move |code_blocks| {
+            let code_blocks = code_blocks
+                .into_iter()
+                .map(|mut code| {
+                    if is_rust_file
+                        && code.language.as_ref().map(|l| l.as_str()) == Some("suggestion")
+                    {
+                        code.language = Some("rust".to_string());
+                    }
+                    code
+                }).collect();
+            check_samples(from, None, code_blocks, action, options)
+        }),
+    )
+}
+
+pub fn check_latest(
+    owner: &str,
+    repo: &str,
+    from_ts: i64,
+    action: Action,
+    options: Options,
+    token: &str,
+) -> impl Future<Item = Vec<CodeAndResults>, Error = failure::Error> {
+    let token2 = Some(token.to_string());
+    github::graphql_issue_list::graphql(owner, repo, from_ts, token)
+        .map(|response| response.list())
+        .and_then(move |issue_and_comments| {
+            future::join_all(
+                issue_and_comments
+                    .into_iter()
+                    .filter(move |comm| comm.last_update.timestamp() > from_ts)
+                    .filter(|comm| !comm.body.starts_with(github::issue::BOT_COMMENT_MARKER))
+                    .map(|comm| {
+                        check_markdown(
+                            comm.url,
+                            Some(comm.last_update),
+                            &comm.body,
+                            action,
+                            options,
+                            &token2,
+                        )
+                    }).collect::<Vec<_>>(),
+            )
+        }).map(flatten)
+}
+
+fn flatten(code_blocks: Vec<Vec<CodeAndResults>>) -> Vec<CodeAndResults> {
+    code_blocks.into_iter().flat_map(|cbs| cbs).collect()
+}
+
+impl Results {
+    pub fn to_markdown(&self, options: Options) -> String {
+        match self {
+            Results::Actions(results) => results
+                .iter()
+                .map(|result| {
+                    details(
+                        &format!("{} on {}", result.action.name(), options.channel.name()),
+                        &result.output,
+                    )
+                }).collect::<Vec<_>>()
+                .concat(),
+            Results::Matrix(matrix) => iter::once(format!("{}\n\n", matrix.summary))
+                .chain(matrix.results.iter().map(|result| {
+                    details(
+                        &format!(
+                            "{} ({})",
+                            result.channel.name(),
+                            if result.success { "success" } else { "failure" }
+                        ),
+                        &result.output,
+                    )
+                })).collect::<Vec<_>>()
+                .concat(),
+            Results::Verdict(verdict) => format!(
+                "Verdict: **{}**\n\n{}{}",
+                verdict.verdict.name(),
+                if verdict.diff.is_empty() {
+                    String::new()
+                } else {
+                    details("difference with the expected output", &verdict.diff)
+                },
+                details(
+                    &format!("run on {}", options.channel.name()),
+                    &verdict.output
+                )
+            ),
+        }
+    }
+}
+
+fn details(summary: &str, output: &str) -> String {
+    format!(
+        "<details><summary>{}</summary>\n\n```\n{}\n```\n\n</details>\n\n",
+        summary,
+        output.trim_right()
+    )
+}
+
+pub fn render_comment(code_blocks: &[CodeAndResults], options: Options) -> String {
+    let checked = code_blocks
+        .iter()
+        .filter(|code_block| code_block.results.is_some())
+        .collect::<Vec<_>>();
+    if checked.is_empty() {
+        return "No Rust code sample found.\n".to_string();
+    }
+    iter::once("Results from the Rust playground:\n\n".to_string())
+        .chain(checked.iter().map(|code_block| {
+            format!(
+                "From {}\n\n```rust\n{}\n```\n\n{}{}",
+                code_block.from,
+                code_block.code.trim_right(),
+                code_block
+                    .files
+                    .iter()
+                    .map(|file| format!(
+                        "`{}`\n\n```\n{}\n```\n\n",
+                        file.name,
+                        file.content.trim_right()
+                    )).collect::<Vec<_>>()
+                    .concat(),
+                code_block
+                    .results
+                    .as_ref()
+                    .map(|results| results.to_markdown(options))
+                    .unwrap_or_default()
+            )
+        })).collect::<Vec<_>>()
+        .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn issue(body: &str) -> github::issue::Issue {
+        serde_json::from_value(json!({
+            "url": "https://api.github.com/repos/owner/repo/issues/1",
+            "id": 1,
+            "node_id": "issue",
+            "number": 1,
+            "html_url": "https://github.com/owner/repo/issues/1",
+            "comments_url": "https://api.github.com/repos/owner/repo/issues/1/comments",
+            "comments": 0,
+            "created_at": "2018-11-01T00:00:00Z",
+            "updated_at": "2018-11-01T00:00:00Z",
+            "state": "open",
+            "title": "sample",
+            "body": body,
+            "pull_request": null,
+        })).unwrap()
+    }
+
+    fn comment(id: u64, body: &str) -> github::issue::Comment {
+        serde_json::from_value(json!({
+            "url": format!("https://api.github.com/repos/owner/repo/issues/comments/{}", id),
+            "id": id,
+            "node_id": "comment",
+            "html_url": format!("https://github.com/owner/repo/issues/1#issuecomment-{}", id),
+            "issue_url": "https://api.github.com/repos/owner/repo/issues/1",
+            "created_at": "2018-11-01T00:00:00Z",
+            "updated_at": "2018-11-01T00:00:00Z",
+            "body": body,
+        })).unwrap()
+    }
+
+    /// The comment the bot would post for these texts, every sample running fine
+    fn bot_comment(texts: Vec<(String, String)>) -> String {
+        let code_blocks = texts
+            .into_iter()
+            .flat_map(|(from, text)| {
+                markdown::get_code_samples(&text, &None)
+                    .wait()
+                    .unwrap()
+                    .into_iter()
+                    .map(move |code| CodeAndResults {
+                        from: from.clone(),
+                        results: if code.is_rust() {
+                            Some(Results::Actions(vec![ActionResult {
+                                action: playground::Action::Run,
+                                success: true,
+                                output: "Hello".to_string(),
+                                diagnostics: vec![],
+                            }]))
+                        } else {
+                            None
+                        },
+                        code: code.code,
+                        files: code.files,
+                        ts: None,
+                    })
+            }).collect::<Vec<_>>();
+        render_comment(&code_blocks, Options::default())
+    }
+
+    #[test]
+    fn recheck_leaves_bot_comment_unchanged() {
+        let issue = issue("```rust\nfn main() {\n    println!(\"Hello\");\n}\n```");
+        let reply = comment(
+            2,
+            "Same with\n\n```rust\nfn main() {\n    print!(\"Hello\");\n}\n```",
+        );
+
+        let first = bot_comment(issue_texts(issue.clone(), vec![reply.clone()]));
+        let posted = comment(
+            3,
+            &format!("{}\n{}", github::issue::BOT_COMMENT_MARKER, first),
+        );
+        let second = bot_comment(issue_texts(issue, vec![reply, posted]));
+
+        assert_eq!(first.matches("From ").count(), 2);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn issue_without_description() {
+        let mut issue = serde_json::to_value(issue("")).unwrap();
+        issue["body"] = json!(null);
+        let issue: github::issue::Issue = serde_json::from_value(issue).unwrap();
+        let texts = issue_texts(issue, vec![comment(2, "reply")]);
+        assert_eq!(texts[0].1, "");
+        assert_eq!(texts[1].1, "reply");
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use hex;
+use serde_json;
+use sha1::{Digest, Sha1};
+
+/// An answer from Github with its ETag, kept to make conditional requests
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub url: String,
+    pub etag: String,
+    /// Link to the next page if the answer was paginated
+    pub next: Option<String>,
+    /// JSON body of the answer
+    pub body: String,
+    /// Timestamp of the answer
+    pub stored_at: i64,
+}
+
+/// Where answers from Github are kept between requests
+pub trait Cache: Send + Sync {
+    fn get(&self, url: &str) -> Option<Entry>;
+
+    fn insert(&self, entry: Entry);
+
+    fn usage(&self) -> Usage;
+}
+
+/// Limits after which entries are dropped, least recently used first
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub max_entries: usize,
+    /// Total size of the bodies kept
+    pub max_bytes: usize,
+    pub max_age: Duration,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds {
+            max_entries: 10_000,
+            max_bytes: 64 * 1_048_576,
+            max_age: Duration::new(7 * 24 * 3600, 0),
+        }
+    }
+}
+
+/// Number of entries and size of their bodies
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+static HITS: AtomicUsize = AtomicUsize::new(0);
+static MISSES: AtomicUsize = AtomicUsize::new(0);
+static NOT_MODIFIED: AtomicUsize = AtomicUsize::new(0);
+static EVICTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters since the start of the process, and the current usage of the cache
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Stats {
+    /// Requests made with the ETag of a cached answer
+    pub hits: usize,
+    /// Requests for an answer not in the cache, or expired
+    pub misses: usize,
+    /// Cached answers that Github said were still valid
+    pub not_modified: usize,
+    /// Answers dropped because of the bounds
+    pub evictions: usize,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        not_modified: NOT_MODIFIED.load(Ordering::Relaxed),
+        evictions: EVICTIONS.load(Ordering::Relaxed),
+        usage: cache().usage(),
+    }
+}
+
+/// Count an answer served from the cache after a `304 Not Modified`
+pub fn count_not_modified() {
+    NOT_MODIFIED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Entries in memory. Hits, misses and evictions are counted by the caches using them, so that
+/// reloading a `Disk` cache is not counted.
+#[derive(Default)]
+struct Entries {
+    /// Entries with the tick of their last use
+    by_url: HashMap<String, (Entry, u64)>,
+    /// URLs by the tick of their last use, least recently used first
+    by_use: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+}
+
+impl Entries {
+    /// The entry for `url`, and whether it expired and was removed
+    fn get(&mut self, url: &str, bounds: Bounds) -> (Option<Entry>, bool) {
+        let expired = match self.by_url.get(url) {
+            Some(&(ref entry, _)) => is_expired(entry, bounds),
+            None => return (None, false),
+        };
+        if expired {
+            self.remove(url);
+            return (None, true);
+        }
+        self.tick += 1;
+        let tick = self.tick;
+        let &mut (ref entry, ref mut last_use) = self.by_url.get_mut(url).unwrap();
+        self.by_use.remove(last_use);
+        self.by_use.insert(tick, url.to_string());
+        *last_use = tick;
+        (Some(entry.clone()), false)
+    }
+
+    /// Add an entry and return the URLs of the entries dropped to make room for it
+    fn insert(&mut self, entry: Entry, bounds: Bounds) -> Vec<String> {
+        let url = entry.url.clone();
+        self.remove(&url);
+        self.tick += 1;
+        self.bytes += entry.body.len();
+        self.by_use.insert(self.tick, url.clone());
+        self.by_url.insert(url, (entry, self.tick));
+        let mut evicted = vec![];
+        while self.bytes > bounds.max_bytes || self.by_url.len() > bounds.max_entries {
+            // an answer bigger than the bound on its own is not kept either
+            let least_recent = match self.by_use.values().next() {
+                Some(least_recent) => least_recent.clone(),
+                None => break,
+            };
+            self.remove(&least_recent);
+            evicted.push(least_recent);
+        }
+        evicted
+    }
+
+    fn remove(&mut self, url: &str) {
+        if let Some((entry, last_use)) = self.by_url.remove(url) {
+            self.by_use.remove(&last_use);
+            self.bytes -= entry.body.len();
+        }
+    }
+
+    fn usage(&self) -> Usage {
+        Usage {
+            entries: self.by_url.len(),
+            bytes: self.bytes,
+        }
+    }
+}
+
+fn count_get(entry: &Option<Entry>, expired: bool) {
+    if entry.is_some() {
+        HITS.fetch_add(1, Ordering::Relaxed);
+    } else {
+        MISSES.fetch_add(1, Ordering::Relaxed);
+    }
+    if expired {
+        EVICTIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn count_evictions(evicted: &[String]) {
+    EVICTIONS.fetch_add(evicted.len(), Ordering::Relaxed);
+}
+
+fn is_expired(entry: &Entry, bounds: Bounds) -> bool {
+    Utc::now().timestamp() - entry.stored_at > bounds.max_age.as_secs() as i64
+}
+
+/// Cache lost when the process stops, the default
+pub struct Memory {
+    bounds: Bounds,
+    entries: Mutex<Entries>,
+}
+
+impl Memory {
+    pub fn new(bounds: Bounds) -> Self {
+        Memory {
+            bounds,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+}
+
+impl Cache for Memory {
+    fn get(&self, url: &str) -> Option<Entry> {
+        let (entry, expired) = self.entries.lock().unwrap().get(url, self.bounds);
+        count_get(&entry, expired);
+        entry
+    }
+
+    fn insert(&self, entry: Entry) {
+        let evicted = self.entries.lock().unwrap().insert(entry, self.bounds);
+        count_evictions(&evicted);
+    }
+
+    fn usage(&self) -> Usage {
+        self.entries.lock().unwrap().usage()
+    }
+}
+
+/// Cache kept in a directory, one JSON file per URL, and reloaded when opened
+pub struct Disk {
+    directory: PathBuf,
+    bounds: Bounds,
+    entries: Mutex<Entries>,
+}
+
+impl Disk {
+    pub fn open(directory: &Path, bounds: Bounds) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let mut loaded = vec![];
+        for dir_entry in fs::read_dir(directory)? {
+            let path = dir_entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let entry = fs::read(&path)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<Entry>(&content).ok());
+            match entry {
+                Some(ref entry) if !is_expired(entry, bounds) => loaded.push(entry.clone()),
+                _ => remove_file(&path),
+            }
+        }
+        // the newest entries are kept if the bounds changed since they were written, and the
+        // entries dropped then are not counted as evictions
+        loaded.sort_by_key(|entry| entry.stored_at);
+        let disk = Disk {
+            directory: directory.to_path_buf(),
+            bounds,
+            entries: Mutex::new(Entries::default()),
+        };
+        {
+            let mut entries = disk.entries.lock().unwrap();
+            for entry in loaded {
+                for evicted in entries.insert(entry, bounds) {
+                    remove_file(&disk.path(&evicted));
+                }
+            }
+            info!(
+                "loaded {} Github answers from {}",
+                entries.usage().entries,
+                directory.display()
+            );
+        }
+        Ok(disk)
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        let mut hasher = Sha1::new();
+        hasher.input(url.as_bytes());
+        self.directory
+            .join(format!("{}.json", hex::encode(hasher.result())))
+    }
+}
+
+impl Cache for Disk {
+    fn get(&self, url: &str) -> Option<Entry> {
+        let (entry, expired) = self.entries.lock().unwrap().get(url, self.bounds);
+        count_get(&entry, expired);
+        if expired {
+            remove_file(&self.path(url));
+        }
+        entry
+    }
+
+    fn insert(&self, entry: Entry) {
+        let path = self.path(&entry.url);
+        let evicted = {
+            let mut entries = self.entries.lock().unwrap();
+            let written = serde_json::to_vec(&entry)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+                .and_then(|content| fs::write(&path, content));
+            if let Err(err) = written {
+                warn!("could not write {}: {}", path.display(), err);
+            }
+            entries.insert(entry, self.bounds)
+        };
+        count_evictions(&evicted);
+        for url in evicted {
+            remove_file(&self.path(&url));
+        }
+    }
+
+    fn usage(&self) -> Usage {
+        self.entries.lock().unwrap().usage()
+    }
+}
+
+fn remove_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            warn!("could not remove {}: {}", path.display(), err);
+        }
+    }
+}
+
+lazy_static! {
+    static ref CACHE: RwLock<Arc<Cache>> =
+        { RwLock::new(Arc::new(Memory::new(Bounds::default()))) };
+}
+
+/// Replace the cache used for requests to Github, in memory by default
+pub fn set_cache<C: Cache + 'static>(cache: C) {
+    *CACHE.write().unwrap() = Arc::new(cache);
+}
+
+pub fn cache() -> Arc<Cache> {
+    CACHE.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, body: &str, age: i64) -> Entry {
+        Entry {
+            url: url.to_string(),
+            etag: format!("\"{}\"", url),
+            next: None,
+            body: body.to_string(),
+            stored_at: Utc::now().timestamp() - age,
+        }
+    }
+
+    fn bounds(max_entries: usize, max_bytes: usize) -> Bounds {
+        Bounds {
+            max_entries,
+            max_bytes,
+            max_age: Duration::new(3600, 0),
+        }
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted_first() {
+        let bounds = bounds(2, 1000);
+        let mut entries = Entries::default();
+        assert!(entries.insert(entry("a", "1", 0), bounds).is_empty());
+        assert!(entries.insert(entry("b", "2", 0), bounds).is_empty());
+        assert!(entries.get("a", bounds).0.is_some());
+        assert_eq!(entries.insert(entry("c", "3", 0), bounds), vec!["b"]);
+        assert!(entries.get("b", bounds).0.is_none());
+        assert_eq!(entries.insert(entry("d", "4", 0), bounds), vec!["a"]);
+        assert_eq!(entries.usage().entries, 2);
+    }
+
+    #[test]
+    fn bytes_are_accounted() {
+        let bounds = bounds(10, 10);
+        let mut entries = Entries::default();
+        entries.insert(entry("a", "1234", 0), bounds);
+        entries.insert(entry("b", "1234", 0), bounds);
+        assert_eq!(entries.usage().bytes, 8);
+        // replacing an entry only counts its new body
+        entries.insert(entry("a", "12", 0), bounds);
+        assert_eq!(entries.usage().bytes, 6);
+        assert_eq!(entries.insert(entry("c", "12345", 0), bounds), vec!["b"]);
+        assert_eq!(entries.usage().bytes, 7);
+        // an answer bigger than the bound is not kept
+        let evicted = entries.insert(entry("d", "12345678901", 0), bounds);
+        assert_eq!(evicted, vec!["a", "c", "d"]);
+        assert_eq!(entries.usage().entries, 0);
+        assert_eq!(entries.usage().bytes, 0);
+    }
+
+    #[test]
+    fn expired_entries_are_removed() {
+        let bounds = bounds(10, 1000);
+        let mut entries = Entries::default();
+        entries.insert(entry("old", "1", 7200), bounds);
+        entries.insert(entry("new", "2", 0), bounds);
+        let (old, expired) = entries.get("old", bounds);
+        assert!(old.is_none());
+        assert!(expired);
+        let (old, expired) = entries.get("old", bounds);
+        assert!(old.is_none());
+        assert!(!expired);
+        assert!(entries.get("new", bounds).0.is_some());
+        assert_eq!(entries.usage().entries, 1);
+        assert_eq!(entries.usage().bytes, 1);
+    }
+
+    #[test]
+    fn disk_is_reloaded_without_counting_evictions() {
+        let directory =
+            ::std::env::temp_dir().join(format!("github-cache-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        {
+            let disk = Disk::open(&directory, bounds(10, 1000)).unwrap();
+            // written directly so that the counters are left alone
+            let mut entries = disk.entries.lock().unwrap();
+            for entry in vec![
+                entry("a", "1", 30),
+                entry("b", "2", 20),
+                entry("c", "3", 10),
+            ] {
+                fs::write(disk.path(&entry.url), serde_json::to_vec(&entry).unwrap()).unwrap();
+                entries.insert(entry, disk.bounds);
+            }
+            let expired = entry("d", "4", 7200);
+            fs::write(
+                disk.path(&expired.url),
+                serde_json::to_vec(&expired).unwrap(),
+            ).unwrap();
+        }
+        let evictions = EVICTIONS.load(Ordering::Relaxed);
+        let disk = Disk::open(&directory, bounds(2, 1000)).unwrap();
+        assert_eq!(EVICTIONS.load(Ordering::Relaxed), evictions);
+        assert_eq!(disk.usage().entries, 2);
+        for url in &["a", "d"] {
+            assert!(!disk.path(url).exists());
+        }
+        for url in &["b", "c"] {
+            assert!(disk.path(url).exists());
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+use base64;
+use failure;
+use futures::future::Future;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Contents {
+    pub name: String,
+    pub path: String,
+    pub sha: String,
+    pub html_url: String,
+    pub encoding: Option<String>,
+    pub content: Option<String>,
+}
+
+impl Contents {
+    /// Decoded content of the file
+    pub fn text(&self) -> Result<String, failure::Error> {
+        let content = self
+            .content
+            .as_ref()
+            .ok_or_else(|| failure::err_msg(format!("{} is not a file", self.path)))?;
+        match self.encoding.as_ref().map(|encoding| encoding.as_str()) {
+            Some("base64") => {
+                let content = content
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect::<String>();
+                Ok(String::from_utf8(base64::decode(&content)?)?)
+            }
+            _ => Ok(content.clone()),
+        }
+    }
+}
+
+/// A link to a file in a repository, possibly to some of its lines
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileLink {
+    pub owner: String,
+    pub repo: String,
+    /// Commit, branch or tag. Only its first segment is kept for a branch with a `/` in its name,
+    /// the rest being taken as the path.
+    pub reference: String,
+    pub path: String,
+    /// First and last lines, starting at 1
+    pub lines: Option<(usize, usize)>,
+}
+
+impl FileLink {
+    /// Parse links like `https://github.com/owner/repo/blob/sha/src/foo.rs#L10-L40` or
+    /// `https://raw.githubusercontent.com/owner/repo/sha/src/foo.rs`. A link to a branch named
+    /// like `feature/foo` cannot be told apart from a link to the `foo` directory of a `feature`
+    /// branch without asking Github, and is read as the latter.
+    pub fn parse(url: &str) -> Option<FileLink> {
+        let url = url
+            .trim_left_matches("https://")
+            .trim_left_matches("http://");
+        let (url, fragment) = match url.find('#') {
+            Some(hash) => (&url[..hash], Some(&url[hash + 1..])),
+            None => (url, None),
+        };
+        let url = url.split('?').next().unwrap_or(url);
+        let mut parts = url.split('/');
+        let host = parts.next()?;
+        let owner = parts.next()?;
+        let repo = parts.next()?;
+        let is_file = match host {
+            "github.com" | "www.github.com" => parts.next() == Some("blob"),
+            "raw.githubusercontent.com" => true,
+            _ => false,
+        };
+        if !is_file {
+            return None;
+        }
+        let reference = parts.next()?;
+        let path = parts.collect::<Vec<_>>().join("/");
+        if owner.is_empty() || repo.is_empty() || reference.is_empty() || path.is_empty() {
+            return None;
+        }
+        Some(FileLink {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            reference: reference.to_string(),
+            path,
+            lines: fragment.and_then(parse_lines),
+        })
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Line range from fragments like `L10-L40` or `L10`
+fn parse_lines(fragment: &str) -> Option<(usize, usize)> {
+    let mut bounds = fragment.split('-').map(|bound| {
+        bound
+            .trim_left_matches('L')
+            .split('C')
+            .next()
+            .and_then(|line| line.parse::<usize>().ok())
+    });
+    let first = bounds.next()??;
+    let last = match bounds.next() {
+        Some(last) => last?,
+        None => first,
+    };
+    if first == 0 || last < first {
+        return None;
+    }
+    Some((first, last))
+}
+
+pub fn get_contents(
+    owner: &str,
+    repo: &str,
+    path: &str,
+    reference: &str,
+    token: Option<String>,
+) -> Box<Future<Item = Contents, Error = failure::Error>> {
+    super::get_object(
+        &format!(
+            "https://api.github.com/repos/{}/{}/contents/{}?ref={}",
+            owner, repo, path, reference
+        ),
+        token,
+    )
+}
+
+/// Content of a linked file, restricted to the linked lines
+pub fn get_linked_file(
+    link: &FileLink,
+    token: Option<String>,
+) -> impl Future<Item = String, Error = failure::Error> {
+    let lines = link.lines;
+    get_contents(&link.owner, &link.repo, &link.path, &link.reference, token).and_then(
+        move |contents| -> Result<String, failure::Error> {
+            Ok(select_lines(contents.text()?, lines))
+        },
+    )
+}
+
+/// Lines of a file from `first` to `last`, as many as there are when the range goes past its end
+fn select_lines(text: String, lines: Option<(usize, usize)>) -> String {
+    match lines {
+        Some((first, last)) => text
+            .lines()
+            .skip(first - 1)
+            .take(last - first + 1)
+            .map(|line| format!("{}\n", line))
+            .collect::<Vec<_>>()
+            .concat(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(reference: &str, path: &str, lines: Option<(usize, usize)>) -> Option<FileLink> {
+        Some(FileLink {
+            owner: "owner".to_string(),
+            repo: "repo".to_string(),
+            reference: reference.to_string(),
+            path: path.to_string(),
+            lines,
+        })
+    }
+
+    #[test]
+    fn links() {
+        let cases = vec![
+            (
+                "https://github.com/owner/repo/blob/0123abc/src/foo.rs",
+                link("0123abc", "src/foo.rs", None),
+            ),
+            (
+                "http://www.github.com/owner/repo/blob/master/src/foo.rs#L10",
+                link("master", "src/foo.rs", Some((10, 10))),
+            ),
+            (
+                "https://github.com/owner/repo/blob/master/src/foo.rs?plain=1#L10-L40",
+                link("master", "src/foo.rs", Some((10, 40))),
+            ),
+            (
+                "https://github.com/owner/repo/blob/master/src/foo.rs#L10C5-L40C2",
+                link("master", "src/foo.rs", Some((10, 40))),
+            ),
+            (
+                "https://raw.githubusercontent.com/owner/repo/v1.0/Cargo.toml",
+                link("v1.0", "Cargo.toml", None),
+            ),
+            (
+                "https://github.com/owner/repo/blob/master/README.md",
+                link("master", "README.md", None),
+            ),
+            // not a line range
+            (
+                "https://github.com/owner/repo/blob/master/src/foo.rs#L40-L10",
+                link("master", "src/foo.rs", None),
+            ),
+            (
+                "https://github.com/owner/repo/blob/master/src/foo.rs#L0",
+                link("master", "src/foo.rs", None),
+            ),
+            (
+                "https://github.com/owner/repo/blob/master/src/foo.rs#readme",
+                link("master", "src/foo.rs", None),
+            ),
+            // the branch is `feature/foo`, but this cannot be known from the link
+            (
+                "https://github.com/owner/repo/blob/feature/foo/src/lib.rs",
+                link("feature", "foo/src/lib.rs", None),
+            ),
+            // not files
+            ("https://github.com/owner/repo", None),
+            ("https://github.com/owner/repo/issues/1", None),
+            ("https://github.com/owner/repo/tree/master/src", None),
+            ("https://github.com/owner/repo/blob/master", None),
+            ("https://gitlab.com/owner/repo/blob/master/src/foo.rs", None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(FileLink::parse(url), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn linked_lines() {
+        let text = || "1\n2\n3\n4\n".to_string();
+        assert_eq!(select_lines(text(), None), "1\n2\n3\n4\n");
+        assert_eq!(select_lines(text(), Some((2, 3))), "2\n3\n");
+        assert_eq!(select_lines(text(), Some((3, 10))), "3\n4\n");
+        assert_eq!(select_lines(text(), Some((10, 20))), "");
+    }
+}
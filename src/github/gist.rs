@@ -1,14 +1,8 @@
 use std::collections::HashMap;
-use std::ops::Deref;
-use std::sync::RwLock;
 
 use failure;
 use futures::future::Future;
 
-lazy_static! {
-    static ref GIST_CACHE: RwLock<HashMap<super::ETag, Gist>> = { RwLock::new(HashMap::new()) };
-}
-
 #[derive(Deserialize, Debug, Clone)]
 pub struct File {
     pub filename: String,
@@ -31,9 +25,5 @@ pub fn get_gist(
     gist_id: &str,
     token: Option<String>,
 ) -> Box<Future<Item = Gist, Error = failure::Error>> {
-    super::get_object(
-        &format!("https://api.github.com/gists/{}", gist_id),
-        token,
-        GIST_CACHE.deref(),
-    )
+    super::get_object(&format!("https://api.github.com/gists/{}", gist_id), token)
 }
@@ -3,15 +3,59 @@ use std::iter;
 use actix_web::{client, HttpMessage};
 use chrono::prelude::*;
 use failure;
-use futures::future::Future;
-use http::header::{AUTHORIZATION, USER_AGENT};
-
-macro_rules! query {
-    () => {
-r#"{{
-    "query":"{{repository(owner: \"{}\", name: \"{}\") {{issues(first: 100, states: OPEN, orderBy: {{field: UPDATED_AT, direction: DESC}}) {{nodes {{number url title url body lastEditedAt createdAt updatedAt comments(last: 100) {{nodes {{url body lastEditedAt createdAt updatedAt}}}}}}}}}}}}"
-}}"#
-    };
+use futures::future::{self, Future, Loop};
+use http::{
+    header::{AUTHORIZATION, USER_AGENT},
+    StatusCode,
+};
+use serde::Deserialize;
+
+const ISSUES_QUERY: &str = "query($owner: String!, $name: String!, $after: String) {
+    repository(owner: $owner, name: $name) {
+        issues(
+            first: 100, after: $after, states: OPEN,
+            orderBy: {field: UPDATED_AT, direction: DESC}
+        ) {
+            pageInfo {hasNextPage endCursor}
+            nodes {
+                number url title body lastEditedAt createdAt updatedAt
+                comments(first: 100) {
+                    pageInfo {hasNextPage endCursor}
+                    nodes {url body lastEditedAt createdAt updatedAt}
+                }
+            }
+        }
+    }
+}";
+
+const COMMENTS_QUERY: &str =
+    "query($owner: String!, $name: String!, $number: Int!, $after: String) {
+    repository(owner: $owner, name: $name) {
+        issue(number: $number) {
+            comments(first: 100, after: $after) {
+                pageInfo {hasNextPage endCursor}
+                nodes {url body lastEditedAt createdAt updatedAt}
+            }
+        }
+    }
+}";
+
+/// Largest answer accepted for a page of 100 issues with their first 100 comments
+const BODY_LIMIT: usize = 16_777_216;
+
+#[derive(Serialize)]
+struct Query<'a> {
+    query: &'a str,
+    variables: Variables,
+}
+
+#[derive(Serialize, Clone)]
+struct Variables {
+    owner: String,
+    name: String,
+    after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<u32>,
 }
 
 use serde_json;
@@ -44,9 +88,17 @@ pub struct Repository {
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Issues {
+    pub page_info: PageInfo,
     pub nodes: Vec<Issue>,
 }
 
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub end_cursor: Option<String>,
+}
+
 // #[derive(Deserialize, Serialize)]
 // #[serde(rename_all = "camelCase")]
 // pub struct IssueNode {
@@ -70,9 +122,30 @@ pub struct Issue {
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Comments {
+    pub page_info: PageInfo,
     pub nodes: Vec<Comment>,
 }
 
+#[derive(Deserialize)]
+struct CommentsReply {
+    data: IssueRepositoryNode,
+}
+
+#[derive(Deserialize)]
+struct IssueRepositoryNode {
+    repository: IssueNode,
+}
+
+#[derive(Deserialize)]
+struct IssueNode {
+    issue: IssueComments,
+}
+
+#[derive(Deserialize)]
+struct IssueComments {
+    comments: Comments,
+}
+
 // #[derive(Deserialize, Serialize)]
 // #[serde(rename_all = "camelCase")]
 // pub struct CommentNode {
@@ -90,20 +163,191 @@ pub struct Comment {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Open issues updated since `since` with all their comments, following the cursors of
+/// both lists
 pub fn graphql(
     owner: &str,
     repo: &str,
+    since: i64,
     token: &str,
 ) -> Box<Future<Item = GraphQLReply, Error = failure::Error>> {
+    let variables = Variables {
+        owner: owner.to_string(),
+        name: repo.to_string(),
+        after: None,
+        number: None,
+    };
+    let token = token.to_string();
+    let token2 = token.clone();
+    let variables2 = variables.clone();
+    Box::new(
+        future::loop_fn(
+            (variables, vec![]),
+            move |(variables, mut issues): (Variables, Vec<Issue>)| {
+                post::<GraphQLReply>(ISSUES_QUERY, variables.clone(), &token).map(move |reply| {
+                    let page = reply.data.repository.issues;
+                    // issues come most recently updated first
+                    let outdated = page
+                        .nodes
+                        .last()
+                        .map(|issue| issue.updated_at.timestamp() < since)
+                        .unwrap_or(true);
+                    issues.extend(page.nodes);
+                    match page.page_info.end_cursor {
+                        Some(ref cursor) if page.page_info.has_next_page && !outdated => {
+                            Loop::Continue((
+                                Variables {
+                                    after: Some(cursor.clone()),
+                                    ..variables
+                                },
+                                issues,
+                            ))
+                        }
+                        _ => Loop::Break(issues),
+                    }
+                })
+            },
+        ).and_then(move |issues| {
+            // the last page may hold issues not updated since, their comments are not needed
+            future::join_all(
+                issues
+                    .into_iter()
+                    .filter(|issue| issue.updated_at.timestamp() >= since)
+                    .map(|issue| complete_comments(issue, variables2.clone(), token2.clone()))
+                    .collect::<Vec<_>>(),
+            )
+        }).map(|issues| GraphQLReply {
+            data: RepositoryNode {
+                repository: Repository {
+                    issues: Issues {
+                        page_info: PageInfo {
+                            has_next_page: false,
+                            end_cursor: None,
+                        },
+                        nodes: issues,
+                    },
+                },
+            },
+        }),
+    )
+}
+
+/// Fetch the comments of an issue past the first page
+fn complete_comments(
+    mut issue: Issue,
+    variables: Variables,
+    token: String,
+) -> Box<Future<Item = Issue, Error = failure::Error>> {
+    if !issue.comments.page_info.has_next_page {
+        return Box::new(future::ok(issue));
+    }
+    let variables = Variables {
+        after: issue.comments.page_info.end_cursor.clone(),
+        number: Some(issue.number),
+        ..variables
+    };
+    let first_page = ::std::mem::replace(&mut issue.comments.nodes, vec![]);
+    let comments = future::loop_fn(
+        (variables, first_page),
+        move |(variables, mut comments): (Variables, Vec<Comment>)| {
+            post::<CommentsReply>(COMMENTS_QUERY, variables.clone(), &token).map(move |reply| {
+                let page = reply.data.repository.issue.comments;
+                comments.extend(page.nodes);
+                match page.page_info.end_cursor {
+                    Some(ref cursor) if page.page_info.has_next_page => Loop::Continue((
+                        Variables {
+                            after: Some(cursor.clone()),
+                            ..variables
+                        },
+                        comments,
+                    )),
+                    _ => Loop::Break(comments),
+                }
+            })
+        },
+    );
+    Box::new(comments.map(move |comments| {
+        issue.comments = Comments {
+            page_info: PageInfo {
+                has_next_page: false,
+                end_cursor: None,
+            },
+            nodes: comments,
+        };
+        issue
+    }))
+}
+
+fn post<T>(
+    query: &str,
+    variables: Variables,
+    token: &str,
+) -> Box<Future<Item = T, Error = failure::Error>>
+where
+    for<'de> T: Deserialize<'de>,
+    T: 'static,
+{
     let mut request = client::post("https://api.github.com/graphql");
     request.header(USER_AGENT, "actix");
     request.header(AUTHORIZATION, format!("bearer {}", token));
-    let resp = request.body(format!(query!(), owner, repo)).unwrap().send();
+    let resp = request.json(Query { query, variables }).unwrap().send();
 
-    Box::new(
-        resp.map_err(|err| err.into())
-            .and_then(|resp| resp.json().limit(1_048_576).map_err(|err| err.into())),
-    )
+    Box::new(resp.map_err(|err| err.into()).and_then(|resp| {
+        let status = resp.status();
+        resp.body()
+            .limit(BODY_LIMIT)
+            .map_err(|err| err.into())
+            .and_then(move |body| read_answer(status, &body))
+    }))
+}
+
+#[derive(Deserialize)]
+struct Errors {
+    /// Why a request was refused, like bad credentials
+    message: Option<String>,
+    /// Why a query failed, like an exceeded rate limit or an unknown repository
+    errors: Option<Vec<QueryError>>,
+}
+
+#[derive(Deserialize)]
+struct QueryError {
+    message: String,
+}
+
+/// Data answered by the GraphQL API, or the errors it gave instead
+fn read_answer<T>(status: StatusCode, body: &[u8]) -> Result<T, failure::Error>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let errors = match serde_json::from_slice::<Errors>(body) {
+        Ok(Errors {
+            errors: Some(ref errors),
+            ..
+        }) if !errors.is_empty() => Some(
+            errors
+                .iter()
+                .map(|error| error.message.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        Ok(Errors {
+            message: Some(message),
+            ..
+        }) => Some(message),
+        _ => None,
+    };
+    match (status.is_success(), errors) {
+        (true, None) => Ok(serde_json::from_slice(body)?),
+        (true, Some(errors)) => Err(failure::err_msg(format!(
+            "Github GraphQL API answered with errors: {}",
+            errors
+        ))),
+        (false, errors) => Err(failure::err_msg(format!(
+            "Github GraphQL API answered with {}: {}",
+            status,
+            errors.unwrap_or_else(|| String::from_utf8_lossy(body).into_owned())
+        ))),
+    }
 }
 
 impl GraphQLReply {
@@ -146,3 +390,41 @@ pub struct Body {
     pub last_update: DateTime<Utc>,
     pub url: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(status: StatusCode, body: &str) -> Result<serde_json::Value, String> {
+        read_answer(status, body.as_bytes()).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        assert_eq!(
+            answer(StatusCode::OK, r#"{"data":{"repository":null}}"#),
+            Ok(json!({"data": {"repository": null}}))
+        );
+        assert_eq!(
+            answer(
+                StatusCode::OK,
+                r#"{"data":null,"errors":[{"type":"RATE_LIMITED","message":"API rate limit exceeded"}]}"#
+            ),
+            Err("Github GraphQL API answered with errors: API rate limit exceeded".to_string())
+        );
+        assert_eq!(
+            answer(
+                StatusCode::UNAUTHORIZED,
+                r#"{"message":"Bad credentials","documentation_url":"https://developer.github.com/v4"}"#
+            ),
+            Err("Github GraphQL API answered with 401 Unauthorized: Bad credentials".to_string())
+        );
+        assert_eq!(
+            answer(StatusCode::BAD_GATEWAY, "<html>bad gateway</html>"),
+            Err(
+                "Github GraphQL API answered with 502 Bad Gateway: <html>bad gateway</html>"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -1,17 +1,9 @@
-use std::collections::HashMap;
-use std::ops::Deref;
-use std::sync::RwLock;
-
 use failure;
 use futures::future::Future;
+use http::Method;
 
-lazy_static! {
-    static ref ISSUE_CACHE: RwLock<HashMap<super::ETag, Issue>> = { RwLock::new(HashMap::new()) };
-}
-lazy_static! {
-    static ref COMMENTS_CACHE: RwLock<HashMap<super::ETag, Vec<Comment>>> =
-        { RwLock::new(HashMap::new()) };
-}
+/// Hidden marker starting every comment posted by the bot, used to find it again
+pub const BOT_COMMENT_MARKER: &str = "<!-- beast-glatisant -->";
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -28,7 +20,7 @@ pub struct PullRequest {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Issue {
     pub url: String,
-    pub id: u32,
+    pub id: u64,
     pub node_id: String,
     pub number: u32,
 
@@ -42,14 +34,15 @@ pub struct Issue {
 
     pub state: State,
     pub title: String,
-    pub body: String,
+    /// `None` for an issue opened without a description
+    pub body: Option<String>,
     pub pull_request: Option<PullRequest>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Comment {
     pub url: String,
-    pub id: u32,
+    pub id: u64,
     pub node_id: String,
 
     pub html_url: String,
@@ -68,13 +61,12 @@ pub fn get_comments(
     issue_number: u32,
     token: Option<String>,
 ) -> Box<Future<Item = Vec<Comment>, Error = failure::Error>> {
-    super::get_object(
+    super::get_list(
         &format!(
-            "https://api.github.com/repos/{}/{}/issues/{}/comments",
+            "https://api.github.com/repos/{}/{}/issues/{}/comments?per_page=100",
             owner, repo, issue_number
         ),
         token,
-        COMMENTS_CACHE.deref(),
     )
 }
 
@@ -90,6 +82,79 @@ pub fn get_issue(
             owner, repo, issue_number
         ),
         token,
-        ISSUE_CACHE.deref(),
+    )
+}
+
+#[derive(Serialize)]
+struct CommentBody<'a> {
+    body: &'a str,
+}
+
+pub fn create_comment(
+    owner: &str,
+    repo: &str,
+    issue_number: u32,
+    body: &str,
+    token: String,
+) -> Box<Future<Item = Comment, Error = failure::Error>> {
+    super::send_object(
+        Method::POST,
+        &format!(
+            "https://api.github.com/repos/{}/{}/issues/{}/comments",
+            owner, repo, issue_number
+        ),
+        token,
+        &CommentBody { body },
+    )
+}
+
+pub fn update_comment(
+    owner: &str,
+    repo: &str,
+    comment_id: u64,
+    body: &str,
+    token: String,
+) -> Box<Future<Item = Comment, Error = failure::Error>> {
+    super::send_object(
+        Method::PATCH,
+        &format!(
+            "https://api.github.com/repos/{}/{}/issues/comments/{}",
+            owner, repo, comment_id
+        ),
+        token,
+        &CommentBody { body },
+    )
+}
+
+/// Post `body` as the bot comment of an issue, editing the previous one if there is one
+pub fn upsert_bot_comment(
+    owner: &str,
+    repo: &str,
+    issue_number: u32,
+    body: &str,
+    token: String,
+) -> Box<Future<Item = Comment, Error = failure::Error>> {
+    let owner = owner.to_string();
+    let repo = repo.to_string();
+    let body = format!("{}\n{}", BOT_COMMENT_MARKER, body);
+    Box::new(
+        get_comments(&owner, &repo, issue_number, Some(token.clone())).and_then(move |comments| {
+            match comments
+                .iter()
+                .find(|comment| comment.body.starts_with(BOT_COMMENT_MARKER))
+            {
+                Some(comment) => {
+                    debug!("updating bot comment {}", comment.id);
+                    update_comment(&owner, &repo, comment.id, &body, token)
+                }
+                None => {
+                    debug!(
+                        "creating bot comment on {}/{}#{}",
+                        owner, repo, issue_number
+                    );
+                    create_comment(&owner, &repo, issue_number, &body, token)
+                }
+            }
+        }),
     )
 }
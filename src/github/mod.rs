@@ -1,106 +1,229 @@
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::Arc;
 
 use actix_web::{
     client::{self, ClientResponse},
     HttpMessage,
 };
+use chrono::Utc;
 use failure;
-use futures::future::{self, Future};
+use futures::future::{self, Future, Loop};
 use http::{
-    header::{AUTHORIZATION, ETAG, IF_NONE_MATCH, USER_AGENT},
-    StatusCode,
+    header::{AUTHORIZATION, ETAG, IF_NONE_MATCH, LINK, USER_AGENT},
+    Method, StatusCode,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json;
 
+pub mod cache;
+pub mod contents;
 pub mod gist;
 pub mod graphql_issue_list;
 pub mod issue;
+pub mod pull;
+pub mod webhook;
 
-#[derive(PartialEq, Eq, Hash, Clone)]
-pub struct ETag(String);
-
-lazy_static! {
-    static ref URL_TO_ETAG_CACHE: RwLock<HashMap<String, ETag>> = { RwLock::new(HashMap::new()) };
-}
+/// Largest answer accepted, a page of 100 comments can be well over the default limit
+const BODY_LIMIT: usize = 4_194_304;
 
 pub fn get_object<T>(
     url: &str,
     token: Option<String>,
-    etag_cache: &'static RwLock<HashMap<ETag, T>>,
 ) -> Box<Future<Item = T, Error = failure::Error>>
 where
-    T: Clone,
     for<'de> T: Deserialize<'de>,
+    T: 'static,
 {
-    let cached_etag = URL_TO_ETAG_CACHE.read().unwrap().get(url).cloned();
+    Box::new(get_page(url, token).map(|(object, _)| object))
+}
+
+/// Every item of a paginated list, following the `next` links of the `Link` header
+pub fn get_list<T>(
+    url: &str,
+    token: Option<String>,
+) -> Box<Future<Item = Vec<T>, Error = failure::Error>>
+where
+    for<'de> T: Deserialize<'de>,
+    T: 'static,
+{
+    Box::new(future::loop_fn(
+        (url.to_string(), vec![]),
+        move |(url, mut items): (String, Vec<T>)| {
+            get_page(&url, token.clone()).map(move |(page, next): (Vec<T>, _)| {
+                items.extend(page);
+                match next {
+                    Some(next) => Loop::Continue((next, items)),
+                    None => Loop::Break(items),
+                }
+            })
+        },
+    ))
+}
+
+/// An object and the link to the next page, from the cache if Github says it did not change
+fn get_page<T>(
+    url: &str,
+    token: Option<String>,
+) -> Box<Future<Item = (T, Option<String>), Error = failure::Error>>
+where
+    for<'de> T: Deserialize<'de>,
+    T: 'static,
+{
+    let cache = cache::cache();
+    let cached = cache.get(url);
 
     let mut request = client::get(url);
     request.header(USER_AGENT, "actix");
     if let Some(token) = token {
         request.header(AUTHORIZATION, format!("bearer {}", token));
     }
-    if let Some(ref etag) = cached_etag {
-        request.header(IF_NONE_MATCH, etag.0.clone());
+    if let Some(ref cached) = cached {
+        request.header(IF_NONE_MATCH, cached.etag.clone());
     }
     let resp = request.finish().unwrap().send();
     let key = url.to_string();
-    Box::new(resp.map_err(move |err| err.into()).and_then(move |resp| {
-        match (cached_etag, resp.status()) {
-            (Some(etag), StatusCode::NOT_MODIFIED) => {
-                debug!("retrieved {} from cache", key);
-                get_from_cache(&etag, etag_cache)
-            }
-            (Some(old_etag), _) => {
-                debug!("updating {} in cache", key);
-                etag_cache.write().unwrap().remove(&old_etag);
-                add_to_cache_and_return(key, resp, etag_cache)
-            }
-            (None, _) => {
-                debug!("adding {} to cache", key);
-                add_to_cache_and_return(key, resp, etag_cache)
+    Box::new(
+        resp.map_err(move |err| err.into())
+            .and_then(
+                move |resp| -> Box<Future<Item = (String, Option<String>), Error = failure::Error>> {
+                    match (cached, resp.status()) {
+                        (Some(cached), StatusCode::NOT_MODIFIED) => {
+                            debug!("retrieved {} from cache", key);
+                            cache::count_not_modified();
+                            Box::new(future::ok((cached.body, cached.next)))
+                        }
+                        (_, status) if !status.is_success() => Box::new(future::err(
+                            failure::err_msg(format!("{} answered with {}", key, status)),
+                        )),
+                        (cached, _) => {
+                            if cached.is_some() {
+                                debug!("updating {} in cache", key);
+                            } else {
+                                debug!("adding {} to cache", key);
+                            }
+                            add_to_cache_and_return(key, resp, cache)
+                        }
+                    }
+                },
+            ).and_then(|(body, next)| -> Result<(T, Option<String>), failure::Error> {
+                Ok((serde_json::from_str(&body)?, next))
+            }),
+    )
+}
+
+/// URL of the next page from a header like
+/// `<https://api.github.com/...?page=2>; rel="next", <...?page=5>; rel="last"`
+fn next_page(link: &str) -> Option<String> {
+    link.split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';');
+            let url = params
+                .next()?
+                .trim()
+                .trim_left_matches('<')
+                .trim_right_matches('>');
+            if params.any(|param| param.trim() == "rel=\"next\"") {
+                Some(url.to_string())
+            } else {
+                None
             }
-        }
-    }))
+        }).next()
 }
 
-fn get_from_cache<T>(
-    etag: &ETag,
-    cache: &RwLock<HashMap<ETag, T>>,
+pub fn send_object<B, T>(
+    method: Method,
+    url: &str,
+    token: String,
+    body: &B,
 ) -> Box<Future<Item = T, Error = failure::Error>>
 where
-    T: Clone,
+    B: Serialize,
+    for<'de> T: Deserialize<'de>,
     T: 'static,
 {
-    let cache_reader = cache.read().unwrap();
-    match cache_reader.get(&etag.clone()).cloned() {
-        Some(v) => Box::new(future::ok(v)),
-        None => unreachable!(),
-    }
+    let mut request = client::ClientRequest::build();
+    request.method(method).uri(url);
+    request.header(USER_AGENT, "actix");
+    request.header(AUTHORIZATION, format!("bearer {}", token));
+    let resp = request.json(body).unwrap().send();
+    let key = url.to_string();
+    Box::new(resp.map_err(|err| err.into()).and_then(
+        move |resp| -> Box<Future<Item = T, Error = failure::Error>> {
+            if resp.status().is_success() {
+                Box::new(resp.json().map_err(|err| err.into()))
+            } else {
+                Box::new(future::err(failure::err_msg(format!(
+                    "{} answered with {}",
+                    key,
+                    resp.status()
+                ))))
+            }
+        },
+    ))
 }
 
-fn add_to_cache_and_return<T>(
+fn add_to_cache_and_return(
     key: String,
     resp: ClientResponse,
-    cache: &'static RwLock<HashMap<ETag, T>>,
-) -> Box<Future<Item = T, Error = failure::Error>>
-where
-    T: Clone,
-    for<'de> T: Deserialize<'de>,
-    T: 'static,
-{
-    let new_etag = resp
+    cache: Arc<cache::Cache>,
+) -> Box<Future<Item = (String, Option<String>), Error = failure::Error>> {
+    let etag = resp
         .headers()
         .get(ETAG)
-        .map(|etag| ETag(etag.to_str().unwrap().to_string()));
+        .and_then(|etag| etag.to_str().ok())
+        .map(|etag| etag.to_string());
+    let next = resp
+        .headers()
+        .get(LINK)
+        .and_then(|link| link.to_str().ok())
+        .and_then(next_page);
     Box::new(
-        resp.json()
-            .map(move |object: T| {
-                if let Some(ref etag) = new_etag {
-                    URL_TO_ETAG_CACHE.write().unwrap().insert(key, etag.clone());
-                    cache.write().unwrap().insert(etag.clone(), object.clone());
-                }
-                object
-            }).map_err(|err| err.into()),
+        resp.body()
+            .limit(BODY_LIMIT)
+            .map_err(|err| err.into())
+            .and_then(
+                move |body| -> Result<(String, Option<String>), failure::Error> {
+                    let body = String::from_utf8(body.to_vec())?;
+                    if let Some(etag) = etag {
+                        cache.insert(cache::Entry {
+                            url: key,
+                            etag,
+                            next: next.clone(),
+                            body: body.clone(),
+                            stored_at: Utc::now().timestamp(),
+                        });
+                    }
+                    Ok((body, next))
+                },
+            ),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_pages() {
+        let cases = vec![
+            (
+                "<https://api.github.com/repositories/1/issues?page=2>; rel=\"next\", \
+                 <https://api.github.com/repositories/1/issues?page=5>; rel=\"last\"",
+                Some("https://api.github.com/repositories/1/issues?page=2"),
+            ),
+            (
+                "<https://api.github.com/repositories/1/issues?page=1>; rel=\"first\", \
+                 <https://api.github.com/repositories/1/issues?page=4>; rel=\"prev\"",
+                None,
+            ),
+            (
+                "<https://api.github.com/repositories/1/issues?page=3>; type=\"text/html\"; \
+                 rel=\"next\"",
+                Some("https://api.github.com/repositories/1/issues?page=3"),
+            ),
+            ("", None),
+        ];
+        for (link, expected) in cases {
+            assert_eq!(next_page(link).as_ref().map(String::as_str), expected);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use failure;
+use futures::future::Future;
+
+use super::issue::State;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PullRequest {
+    pub url: String,
+    pub id: u64,
+    pub node_id: String,
+    pub number: u32,
+
+    pub html_url: String,
+
+    pub created_at: String,
+    pub updated_at: String,
+
+    pub state: State,
+    pub title: String,
+    pub body: Option<String>,
+}
+
+/// A comment on a line of the diff of a pull request
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReviewComment {
+    pub url: String,
+    pub id: u64,
+    pub node_id: String,
+
+    pub html_url: String,
+
+    /// File the comment is about
+    pub path: String,
+    pub diff_hunk: String,
+
+    pub created_at: String,
+    pub updated_at: String,
+
+    pub body: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Review {
+    pub id: u64,
+    pub node_id: String,
+
+    pub html_url: String,
+
+    /// `APPROVED`, `CHANGES_REQUESTED`, `COMMENTED`, ...
+    pub state: String,
+    pub submitted_at: Option<String>,
+
+    pub body: Option<String>,
+}
+
+pub fn get_pull(
+    owner: &str,
+    repo: &str,
+    pull_number: u32,
+    token: Option<String>,
+) -> Box<Future<Item = PullRequest, Error = failure::Error>> {
+    super::get_object(
+        &format!(
+            "https://api.github.com/repos/{}/{}/pulls/{}",
+            owner, repo, pull_number
+        ),
+        token,
+    )
+}
+
+pub fn get_review_comments(
+    owner: &str,
+    repo: &str,
+    pull_number: u32,
+    token: Option<String>,
+) -> Box<Future<Item = Vec<ReviewComment>, Error = failure::Error>> {
+    super::get_list(
+        &format!(
+            "https://api.github.com/repos/{}/{}/pulls/{}/comments?per_page=100",
+            owner, repo, pull_number
+        ),
+        token,
+    )
+}
+
+pub fn get_reviews(
+    owner: &str,
+    repo: &str,
+    pull_number: u32,
+    token: Option<String>,
+) -> Box<Future<Item = Vec<Review>, Error = failure::Error>> {
+    super::get_list(
+        &format!(
+            "https://api.github.com/repos/{}/{}/pulls/{}/reviews?per_page=100",
+            owner, repo, pull_number
+        ),
+        token,
+    )
+}
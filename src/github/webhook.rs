@@ -0,0 +1,154 @@
+use hex;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use super::issue::{Comment, Issue, BOT_COMMENT_MARKER};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Owner {
+    pub login: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Repository {
+    pub name: String,
+    pub owner: Owner,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Payload {
+    pub action: String,
+    pub issue: Issue,
+    pub comment: Option<Comment>,
+    pub repository: Repository,
+}
+
+impl Payload {
+    /// Url and markdown of the body created or edited by this event, if it should be checked
+    pub fn updated_body(&self, event: &str) -> Option<(&str, &str)> {
+        match (event, self.action.as_str(), &self.comment) {
+            ("issues", "opened", _) | ("issues", "edited", _) => {
+                let body = self.issue.body.as_ref().map(String::as_str);
+                Some((self.issue.html_url.as_str(), body.unwrap_or("")))
+            }
+            ("issue_comment", "created", Some(comment))
+            | ("issue_comment", "edited", Some(comment))
+                if !comment.body.starts_with(BOT_COMMENT_MARKER) =>
+            {
+                Some((comment.html_url.as_str(), comment.body.as_str()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Check the `X-Hub-Signature` header sent by Github against the payload
+pub fn verify_signature(secret: &str, signature: &str, payload: &[u8]) -> bool {
+    if !signature.starts_with("sha1=") {
+        return false;
+    }
+    let signature = match hex::decode(&signature[5..]) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let mut mac = Hmac::<Sha1>::new_varkey(secret.as_bytes()).unwrap();
+    mac.input(payload);
+    mac.verify(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    const PING: &[u8] = br#"{"zen":"Keep it logically awesome."}"#;
+
+    #[test]
+    fn signatures() {
+        let good = "sha1=31798790e579957302561486caa0b660ffc38518";
+        assert!(verify_signature("secret", good, PING));
+        assert!(!verify_signature("other secret", good, PING));
+        assert!(!verify_signature(
+            "secret",
+            good,
+            br#"{"zen":"Keep it logically awesome!"}"#
+        ));
+        for malformed in &[
+            "",
+            "31798790e579957302561486caa0b660ffc38518",
+            "sha256=31798790e579957302561486caa0b660ffc38518",
+            "sha1=not hex",
+            "sha1=31798790",
+        ] {
+            assert!(
+                !verify_signature("secret", malformed, PING),
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    fn payload(action: &str, comment: Option<&str>) -> Payload {
+        serde_json::from_value(json!({
+            "action": action,
+            "issue": {
+                "url": "https://api.github.com/repos/owner/repo/issues/1",
+                "id": 1,
+                "node_id": "issue",
+                "number": 1,
+                "html_url": "https://github.com/owner/repo/issues/1",
+                "comments_url": "https://api.github.com/repos/owner/repo/issues/1/comments",
+                "comments": 1,
+                "created_at": "2018-11-01T00:00:00Z",
+                "updated_at": "2018-11-01T00:00:00Z",
+                "state": "open",
+                "title": "sample",
+                "body": null,
+                "pull_request": null,
+            },
+            "comment": comment.map(|body| json!({
+                "url": "https://api.github.com/repos/owner/repo/issues/comments/2",
+                "id": 2,
+                "node_id": "comment",
+                "html_url": "https://github.com/owner/repo/issues/1#issuecomment-2",
+                "issue_url": "https://api.github.com/repos/owner/repo/issues/1",
+                "created_at": "2018-11-01T00:00:00Z",
+                "updated_at": "2018-11-01T00:00:00Z",
+                "body": body,
+            })),
+            "repository": { "name": "repo", "owner": { "login": "owner" } },
+        })).unwrap()
+    }
+
+    #[test]
+    fn updated_bodies() {
+        let issue = Some(("https://github.com/owner/repo/issues/1", ""));
+        let comment = Some((
+            "https://github.com/owner/repo/issues/1#issuecomment-2",
+            "hi",
+        ));
+        let bot_comment = format!("{}\nresults", BOT_COMMENT_MARKER);
+        let cases = vec![
+            ("issues", "opened", None, issue),
+            ("issues", "edited", None, issue),
+            ("issues", "closed", None, None),
+            ("issues", "deleted", None, None),
+            ("issue_comment", "created", Some("hi"), comment),
+            ("issue_comment", "edited", Some("hi"), comment),
+            ("issue_comment", "deleted", Some("hi"), None),
+            ("issue_comment", "created", None, None),
+            ("issue_comment", "edited", Some(bot_comment.as_str()), None),
+            ("pull_request", "opened", None, None),
+        ];
+        for (event, action, comment, expected) in cases {
+            let payload = payload(action, comment);
+            assert_eq!(
+                payload.updated_body(event),
+                expected,
+                "{} {}",
+                event,
+                action
+            );
+        }
+    }
+}
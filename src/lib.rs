@@ -5,20 +5,40 @@ extern crate log;
 extern crate serde_derive;
 extern crate chrono;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 extern crate serde_urlencoded;
 
 extern crate actix_web;
 extern crate failure;
+#[macro_use]
 extern crate futures;
 extern crate http;
+extern crate tokio_timer;
 
 #[macro_use]
 extern crate lazy_static;
+#[cfg(unix)]
+extern crate libc;
+
+extern crate base64;
+extern crate hex;
+extern crate hmac;
+extern crate sha1;
+
+#[macro_use]
+extern crate structopt;
 
 extern crate comrak;
 extern crate linkify;
+extern crate toml;
+
+extern crate rusqlite;
 
+pub mod check;
 pub mod github;
 pub mod markdown;
 pub mod playground;
+pub mod settings;
+pub mod store;
+pub mod verdict;
@@ -0,0 +1,310 @@
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Kind {
+    Rust,
+    /// Commands at a prompt like `$ cargo run`, with their output
+    Shell,
+    /// Errors and warnings from rustc, cargo or Clippy
+    CompilerOutput,
+    Backtrace,
+    Toml,
+    /// Anything else, like JSON or the output of a program
+    Other,
+}
+
+const KINDS: [Kind; 6] = [
+    Kind::Rust,
+    Kind::Shell,
+    Kind::CompilerOutput,
+    Kind::Backtrace,
+    Kind::Toml,
+    Kind::Other,
+];
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+pub struct Guess {
+    pub kind: Kind,
+    /// Between 0 and 1, 0 for `Other` when nothing was recognised
+    pub confidence: f32,
+}
+
+const RUST_PREFIXES: &[&str] = &[
+    "fn ",
+    "pub ",
+    "let ",
+    "impl ",
+    "impl<",
+    "use ",
+    "struct ",
+    "enum ",
+    "trait ",
+    "mod ",
+    "const ",
+    "static ",
+    "type ",
+    "match ",
+    "if let ",
+    "while let ",
+    "for ",
+    "extern crate ",
+    "#[",
+    "#![",
+    "//",
+    "unsafe ",
+    "async ",
+    "macro_rules!",
+];
+
+const RUST_PATTERNS: &[&str] = &["::", "->", "=>", "&mut ", "&self", "println!", "vec!["];
+
+const SHELL_PREFIXES: &[&str] = &["$ ", "% ", "> cargo ", "cargo ", "rustc ", "rustup "];
+
+const COMPILER_PREFIXES: &[&str] = &[
+    "error[E",
+    "error: ",
+    "warning: ",
+    "--> ",
+    "= note: ",
+    "= help: ",
+    "Compiling ",
+    "Finished ",
+    "Running ",
+];
+
+const BACKTRACE_PATTERNS: &[&str] = &["stack backtrace:", "panicked at", "RUST_BACKTRACE"];
+
+/// Guess what an unlabelled code block holds from the shape of its lines. A block starting at a
+/// prompt is a shell session, whatever the output of its commands looks like.
+pub fn guess(code: &str) -> Guess {
+    let mut scores = [0u32; 6];
+    let mut lines = 0;
+    let mut recognised = 0;
+    for line in code.lines().filter(|line| !line.trim().is_empty()) {
+        lines += 1;
+        if let Some((kind, score)) = score_line(line) {
+            recognised += 1;
+            scores[KINDS.iter().position(|k| *k == kind).unwrap()] += score;
+        }
+    }
+    let total: u32 = scores.iter().sum();
+    let best = (0..KINDS.len()).max_by_key(|i| scores[*i]).unwrap();
+    if total == 0 {
+        return Guess {
+            kind: Kind::Other,
+            confidence: 0.0,
+        };
+    }
+    let first_line = code
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty());
+    if first_line.map(is_prompt).unwrap_or(false) {
+        return Guess {
+            kind: Kind::Shell,
+            confidence: recognised as f32 / lines as f32,
+        };
+    }
+    Guess {
+        kind: KINDS[best],
+        confidence: (scores[best] as f32 / total as f32) * (recognised as f32 / lines as f32),
+    }
+}
+
+/// Kind a line points to and how strongly
+fn score_line(line: &str) -> Option<(Kind, u32)> {
+    let trimmed = line.trim();
+    let starts_with_any =
+        |prefixes: &[&str]| prefixes.iter().any(|prefix| trimmed.starts_with(prefix));
+    if BACKTRACE_PATTERNS
+        .iter()
+        .any(|pattern| trimmed.contains(pattern))
+    {
+        return Some((Kind::Backtrace, 3));
+    }
+    if is_frame(trimmed) {
+        return Some((Kind::Backtrace, 2));
+    }
+    if trimmed.starts_with("error[E") || trimmed.starts_with("--> ") {
+        return Some((Kind::CompilerOutput, 3));
+    }
+    if starts_with_any(COMPILER_PREFIXES) || is_gutter(trimmed) {
+        return Some((Kind::CompilerOutput, 2));
+    }
+    if is_prompt(trimmed) {
+        return Some((Kind::Shell, 3));
+    }
+    if starts_with_any(SHELL_PREFIXES) {
+        return Some((Kind::Shell, 2));
+    }
+    if is_table_header(trimmed) {
+        return Some((Kind::Toml, 3));
+    }
+    if starts_with_any(RUST_PREFIXES) {
+        return Some((Kind::Rust, 2));
+    }
+    if is_key_value(trimmed) {
+        return Some((Kind::Toml, 2));
+    }
+    if is_json_member(trimmed) {
+        return Some((Kind::Other, 2));
+    }
+    if RUST_PATTERNS
+        .iter()
+        .any(|pattern| trimmed.contains(pattern))
+        || trimmed.ends_with(';')
+        || trimmed.ends_with('{')
+        || trimmed == "}"
+    {
+        return Some((Kind::Rust, 1));
+    }
+    None
+}
+
+fn is_prompt(line: &str) -> bool {
+    line.starts_with("$ ") || line.starts_with("% ")
+}
+
+/// `"name": value` in a JSON object
+fn is_json_member(line: &str) -> bool {
+    line.starts_with('"') && line[1..].contains("\":")
+}
+
+/// Backtrace frames like `12: std::rt::lang_start` or `at src/main.rs:4`
+fn is_frame(line: &str) -> bool {
+    if line.starts_with("at ") {
+        return line.contains(".rs:");
+    }
+    match line.find(": ") {
+        Some(colon) => {
+            !line[..colon].is_empty() && line[..colon].bytes().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Source lines quoted by rustc, like `4 |     let x = 5;` or `  |     ^ help`
+fn is_gutter(line: &str) -> bool {
+    match line.find('|') {
+        // unlike a closure, the bar is followed by a space
+        Some(bar) => {
+            line[..bar].trim().bytes().all(|c| c.is_ascii_digit())
+                && line[bar + 1..]
+                    .chars()
+                    .next()
+                    .map(|c| c.is_whitespace())
+                    .unwrap_or(true)
+        }
+        None => false,
+    }
+}
+
+/// `[package]`, `[dependencies.serde]` or `[[bin]]`
+fn is_table_header(line: &str) -> bool {
+    let name = line.trim_left_matches('[').trim_right_matches(']');
+    line.starts_with('[')
+        && line.ends_with(']')
+        && !line.starts_with("#[")
+        && !name.is_empty()
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-' || c == b'.')
+}
+
+/// `name = "value"` without the `let` and `;` of a Rust binding
+fn is_key_value(line: &str) -> bool {
+    match line.find(" = ") {
+        Some(equal) => {
+            let key = &line[..equal];
+            !key.is_empty()
+                && key
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-' || c == b'.')
+                && !line.ends_with(';')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guesses() {
+        let cases: &[(&str, Kind)] = &[
+            ("fn main() {\n    println!(\"Hello\");\n}\n", Kind::Rust),
+            (
+                "let v = vec![1, 2, 3];\nfor x in &v {\n    println!(\"{}\", x);\n}\n",
+                Kind::Rust,
+            ),
+            (
+                "#[derive(Debug)]\nstruct Point {\n    x: i32,\n}\n",
+                Kind::Rust,
+            ),
+            ("use std::collections::HashMap;\n", Kind::Rust),
+            (
+                "match c {\n    'a'\n    | 'b' => 1,\n    _ => 0,\n}\n",
+                Kind::Rust,
+            ),
+            (
+                "let f = |x| x + 1;\nlet g = |a, b| a * b;\n",
+                Kind::Rust,
+            ),
+            (
+                "if let Err(e) = run() {\n    eprintln!(\"error: {}\", e);\n}\n",
+                Kind::Rust,
+            ),
+            ("cargo build --release\nrustup default nightly\n", Kind::Shell),
+            (
+                "$ cargo run\n   Compiling foo v0.1.0 (/foo)\n    Finished dev [unoptimized + debuginfo] target(s) in 0.5s\n     Running `target/debug/foo`\nHello\n",
+                Kind::Shell,
+            ),
+            (
+                "error[E0308]: mismatched types\n --> src/main.rs:2:18\n  |\n2 |     let x: u32 = \"a\";\n  |                  ^^^ expected u32, found reference\n",
+                Kind::CompilerOutput,
+            ),
+            (
+                "warning: unused variable: `x`\n --> src/main.rs:2:9\n  |\n  = note: #[warn(unused_variables)] on by default\n",
+                Kind::CompilerOutput,
+            ),
+            (
+                "thread 'main' panicked at 'oops', src/main.rs:2:5\nnote: Run with `RUST_BACKTRACE=1` for a backtrace.\n",
+                Kind::Backtrace,
+            ),
+            (
+                "stack backtrace:\n   0: std::sys::unix::backtrace::tracing::imp::unwind_backtrace\n             at libstd/sys/unix/backtrace/tracing/gcc_s.rs:49\n   1: foo::main\n             at src/main.rs:4\n",
+                Kind::Backtrace,
+            ),
+            (
+                "[dependencies]\nserde = \"1.0\"\nrand = { version = \"0.5\" }\n",
+                Kind::Toml,
+            ),
+            (
+                "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+                Kind::Toml,
+            ),
+            // not Rust
+            ("Hello, world!\n", Kind::Other),
+            ("1\n2\nFizz\n4\nBuzz\n", Kind::Other),
+            ("def add(a, b):\n    return a + b\n", Kind::Other),
+            (
+                "{\n  \"name\": \"foo\",\n  \"values\": [1, 2]\n}\n",
+                Kind::Other,
+            ),
+            ("x = 5\ny = 10\n", Kind::Toml),
+        ];
+        for &(code, kind) in cases {
+            assert_eq!(guess(code).kind, kind, "{}", code);
+        }
+    }
+
+    #[test]
+    fn confidence() {
+        assert_eq!(guess("").confidence, 0.0);
+        assert_eq!(guess("Hello, world!\n").confidence, 0.0);
+        assert_eq!(guess("fn main() {}\n").confidence, 1.0);
+        let mixed = guess("fn main() {\n    let x = 1;\n}\nHello\n");
+        assert_eq!(mixed.kind, Kind::Rust);
+        assert!(mixed.confidence > 0.0 && mixed.confidence < 1.0);
+    }
+}
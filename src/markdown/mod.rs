@@ -0,0 +1,685 @@
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{parse_document, Arena, ComrakOptions};
+use failure;
+use futures::future::{self, Future};
+use http::uri::Uri;
+use linkify::LinkFinder;
+use serde_urlencoded;
+use std::str::FromStr;
+
+use github;
+use playground;
+
+pub mod detect;
+
+#[derive(Clone, Debug)]
+pub struct Code {
+    pub code: String,
+    pub gist_id: Option<String>,
+    pub language: Option<String>,
+    /// Options from the playground link the code comes from
+    pub options: LinkOptions,
+    /// Output block following the sample, usually what the reporter expected to see
+    pub expected: Option<String>,
+    /// Second output block following the sample, usually what the reporter saw instead
+    pub reported: Option<String>,
+    pub attributes: FenceAttributes,
+    /// What an unlabelled block seems to hold
+    pub guess: Option<detect::Guess>,
+    /// `Cargo.toml` given next to the sample
+    pub manifest: Option<String>,
+    /// Other source files of the sample, like the modules of a gist
+    pub files: Vec<playground::SourceFile>,
+}
+impl Code {
+    /// Unlabelled blocks are Rust if they look like it
+    pub fn is_rust(&self) -> bool {
+        match (&self.language, self.guess) {
+            (Some(language), _) => language.to_ascii_lowercase() == "rust",
+            (None, Some(guess)) => guess.kind == detect::Kind::Rust,
+            (None, None) => true,
+        }
+    }
+
+    /// Whether the block is a `Cargo.toml` that may come with a sample
+    fn is_manifest(&self) -> bool {
+        let is_toml = match (&self.language, self.guess) {
+            (Some(language), _) => language.to_ascii_lowercase() == "toml",
+            (None, Some(guess)) => guess.kind == detect::Kind::Toml,
+            (None, None) => false,
+        };
+        is_toml && (self.code.contains("[dependencies") || self.code.contains("[package]"))
+    }
+
+    /// Whether the block is program or compiler output that may follow a sample
+    fn is_output(&self) -> bool {
+        match (&self.language, self.guess) {
+            (Some(language), _) => {
+                OUTPUT_LANGUAGES.contains(&language.to_ascii_lowercase().as_str())
+            }
+            (None, Some(guess)) => {
+                guess.kind != detect::Kind::Rust && guess.kind != detect::Kind::Toml
+            }
+            (None, None) => false,
+        }
+    }
+}
+
+/// Options set in a playground link, the others being the requested ones
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkOptions {
+    pub channel: Option<playground::Channel>,
+    pub mode: Option<playground::Mode>,
+    pub edition: Option<playground::Edition>,
+}
+impl LinkOptions {
+    pub fn apply(&self, options: playground::Options) -> playground::Options {
+        playground::Options {
+            channel: self.channel.unwrap_or(options.channel),
+            mode: self.mode.unwrap_or(options.mode),
+            edition: self.edition.or(options.edition),
+        }
+    }
+}
+
+/// Rustdoc attributes of a fenced code block, like `rust,no_run`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FenceAttributes {
+    pub ignore: bool,
+    pub no_run: bool,
+    pub should_panic: bool,
+    pub compile_fail: bool,
+    pub edition: Option<playground::Edition>,
+}
+impl FenceAttributes {
+    /// Split an info string between the language and the rustdoc attributes. Attributes without
+    /// a language mean Rust.
+    pub fn parse(info: &str) -> (Option<String>, FenceAttributes) {
+        let mut language = None;
+        let mut attributes = FenceAttributes::default();
+        let mut has_attributes = false;
+        for token in info
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty())
+        {
+            let is_attribute = match token {
+                "ignore" => {
+                    attributes.ignore = true;
+                    true
+                }
+                "no_run" => {
+                    attributes.no_run = true;
+                    true
+                }
+                "should_panic" => {
+                    attributes.should_panic = true;
+                    true
+                }
+                "compile_fail" => {
+                    attributes.compile_fail = true;
+                    true
+                }
+                "test_harness" | "allow_fail" => true,
+                _ if token.starts_with("ignore-") => {
+                    attributes.ignore = true;
+                    true
+                }
+                _ if token.starts_with("edition") => {
+                    attributes.edition = token["edition".len()..].parse().ok();
+                    true
+                }
+                _ => is_error_code(token),
+            };
+            has_attributes |= is_attribute;
+            if !is_attribute && language.is_none() {
+                language = Some(token.to_string());
+            }
+        }
+        if has_attributes && language.is_none() {
+            language = Some("rust".to_string());
+        }
+        (language, attributes)
+    }
+
+    /// Action to ask the playground instead of `action`, if any
+    pub fn action(&self, action: playground::Action) -> Option<playground::Action> {
+        match action {
+            _ if self.ignore => None,
+            playground::Action::Run | playground::Action::Test
+                if self.no_run || self.compile_fail =>
+            {
+                Some(playground::Action::Build)
+            }
+            _ => Some(action),
+        }
+    }
+
+    /// Whether the response is what the sample is meant to do, a `should_panic` sample that
+    /// panics being a success
+    pub fn is_success(&self, action: playground::Action, response: &playground::Response) -> bool {
+        let builds = match action {
+            playground::Action::Run | playground::Action::Build | playground::Action::Test => true,
+            playground::Action::Format | playground::Action::Clippy => false,
+        };
+        if builds && self.compile_fail {
+            !response.success
+                && playground::diagnostics::parse(&response.stderr)
+                    .iter()
+                    .any(|diagnostic| diagnostic.level == playground::diagnostics::Level::Error)
+        } else if builds && self.should_panic && action != playground::Action::Build {
+            !response.success && response.stderr.contains("panicked at")
+        } else {
+            response.success
+        }
+    }
+}
+
+/// Error codes like `E0308` given with `compile_fail`
+fn is_error_code(token: &str) -> bool {
+    token.len() > 1 && token.starts_with('E') && token[1..].bytes().all(|c| c.is_ascii_digit())
+}
+
+/// Languages of fenced blocks holding program or compiler output rather than code
+const OUTPUT_LANGUAGES: &[&str] = &["text", "txt", "console", "output", "plain"];
+
+/// A code sample found in the document, either inline or behind a link that is yet to be fetched
+enum Sample {
+    Block(Code),
+    /// Code behind a link, none if it could not be fetched but should not fail the check
+    Link(Box<Future<Item = Option<Code>, Error = failure::Error>>),
+}
+impl Sample {
+    fn fetch(self) -> Box<Future<Item = Option<Code>, Error = failure::Error>> {
+        match self {
+            Sample::Block(code) => Box::new(future::ok(Some(code))),
+            Sample::Link(code) => code,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PlaygroundQueryParams {
+    gist: Option<String>,
+    code: Option<String>,
+    version: Option<String>,
+    mode: Option<String>,
+    edition: Option<String>,
+}
+impl PlaygroundQueryParams {
+    /// Options of the link, or why they cannot be replayed
+    fn options(&self) -> Result<LinkOptions, String> {
+        Ok(LinkOptions {
+            channel: parse_param(&self.version)?,
+            mode: parse_param(&self.mode)?,
+            edition: parse_param(&self.edition)?,
+        })
+    }
+}
+
+fn parse_param<T: FromStr<Err = String>>(param: &Option<String>) -> Result<Option<T>, String> {
+    match param {
+        Some(param) => param.parse().map(Some),
+        None => Ok(None),
+    }
+}
+
+fn is_playground(url: &Uri) -> bool {
+    match playground::playground_url().parse::<Uri>() {
+        Ok(playground) => url.host() == playground.host() && url.port() == playground.port(),
+        Err(_) => false,
+    }
+}
+
+fn playground_link(
+    link: &str,
+    token: &Option<String>,
+) -> Option<Box<Future<Item = Option<Code>, Error = failure::Error>>> {
+    let url = link.parse::<Uri>().ok()?;
+    if !is_playground(&url) {
+        return None;
+    }
+    let query_params =
+        serde_urlencoded::from_str::<PlaygroundQueryParams>(url.query().unwrap_or("")).ok()?;
+    if query_params.code.is_none() && query_params.gist.is_none() {
+        return None;
+    }
+    // a sample that cannot be run as it was shared is skipped rather than run differently
+    let options = match query_params.options() {
+        Ok(options) => options,
+        Err(err) => {
+            warn!(
+                "skipping playground link {}, unsupported option: {}",
+                link, err
+            );
+            return Some(Box::new(future::ok(None)));
+        }
+    };
+    match query_params {
+        PlaygroundQueryParams {
+            code: Some(code), ..
+        } => Some(Box::new(future::ok(Some(Code {
+            code,
+            gist_id: None,
+            language: Some("rust".to_string()),
+            options,
+            expected: None,
+            reported: None,
+            attributes: FenceAttributes::default(),
+            guess: None,
+            manifest: None,
+            files: vec![],
+        })))),
+        PlaygroundQueryParams {
+            gist: Some(gist_id),
+            ..
+        } => Some(Box::new(
+            github::gist::get_gist(&gist_id, token.clone())
+                .and_then(move |gist| gist_sample(gist, options))
+                .map(Some),
+        )),
+        _ => None,
+    }
+}
+
+/// Give each `Cargo.toml` block to the Rust block just before it, or else just after it
+fn associate_manifests(samples: &mut [Sample]) {
+    for i in 0..samples.len() {
+        let manifest = match samples[i] {
+            Sample::Block(ref code) if code.is_manifest() => code.code.clone(),
+            _ => continue,
+        };
+        let neighbour =
+            i.checked_sub(1)
+                .into_iter()
+                .chain(Some(i + 1))
+                .find(|j| match samples.get(*j) {
+                    Some(&Sample::Block(ref code)) => code.is_rust() && code.manifest.is_none(),
+                    _ => false,
+                });
+        if let Some(j) = neighbour {
+            if let Sample::Block(ref mut code) = samples[j] {
+                code.manifest = Some(manifest);
+            }
+        }
+    }
+}
+
+/// Lines of a Rust file or a `Cargo.toml` in a Github repository, from links to the file or its
+/// raw content. Links to other files are not followed, and a file that cannot be fetched is
+/// skipped.
+fn github_file_link(
+    link: &str,
+    token: &Option<String>,
+) -> Option<Box<Future<Item = Option<Code>, Error = failure::Error>>> {
+    let link = github::contents::FileLink::parse(link)?;
+    let language = if link.file_name().ends_with(".rs") {
+        "rust"
+    } else if link.file_name() == "Cargo.toml" {
+        "toml"
+    } else {
+        return None;
+    };
+    let path = link.path.clone();
+    Some(Box::new(
+        github::contents::get_linked_file(&link, token.clone()).then(move |code| match code {
+            Ok(code) => Ok(Some(Code {
+                code,
+                gist_id: None,
+                language: Some(language.to_string()),
+                options: LinkOptions::default(),
+                expected: None,
+                reported: None,
+                attributes: FenceAttributes::default(),
+                guess: None,
+                manifest: None,
+                files: vec![],
+            })),
+            Err(err) => {
+                warn!("skipping linked file {}: {}", path, err);
+                Ok(None)
+            }
+        }),
+    ))
+}
+
+fn linked_code(
+    link: &str,
+    token: &Option<String>,
+) -> Option<Box<Future<Item = Option<Code>, Error = failure::Error>>> {
+    playground_link(link, token).or_else(|| github_file_link(link, token))
+}
+
+/// A sample made of all the files of a gist. The main file is `main.rs`, `lib.rs`, or the Rust
+/// file with a `main` function, other Rust files being modules.
+pub fn gist_sample(gist: github::gist::Gist, options: LinkOptions) -> Result<Code, failure::Error> {
+    let id = gist.id;
+    let mut files = gist
+        .files
+        .into_iter()
+        .map(|(_, file)| file)
+        .collect::<Vec<_>>();
+    files.sort_by(|a, b| a.filename.cmp(&b.filename));
+    let manifest = files
+        .iter()
+        .position(|file| file.filename == "Cargo.toml")
+        .map(|position| files.remove(position).content);
+    let main = files
+        .iter()
+        .position(|file| file.filename == "main.rs")
+        .or_else(|| files.iter().position(|file| file.filename == "lib.rs"))
+        .or_else(|| {
+            files
+                .iter()
+                .position(|file| file.filename.ends_with(".rs") && file.content.contains("fn main"))
+        }).or_else(|| files.iter().position(|file| file.filename.ends_with(".rs")))
+        .or_else(|| files.first().map(|_| 0))
+        .ok_or_else(|| failure::err_msg(format!("gist {} has no file", id)))?;
+    let main = files.remove(main);
+    Ok(Code {
+        code: main.content,
+        gist_id: Some(id),
+        // Rust files are the ones with the `.rs` extension, whatever language Github found
+        language: Some(if main.filename.ends_with(".rs") {
+            "rust".to_string()
+        } else {
+            main.language
+                .filter(|language| !language.eq_ignore_ascii_case("rust"))
+                .unwrap_or_else(|| "text".to_string())
+        }),
+        options,
+        expected: None,
+        reported: None,
+        attributes: FenceAttributes::default(),
+        guess: None,
+        manifest,
+        files: files
+            .into_iter()
+            .map(|file| playground::SourceFile {
+                name: file.filename,
+                content: file.content,
+            }).collect(),
+    })
+}
+
+pub fn get_code_samples(
+    doc: &str,
+    token: &Option<String>,
+) -> Box<Future<Item = Vec<Code>, Error = failure::Error>> {
+    let arena = Arena::new();
+
+    let root = parse_document(&arena, doc, &ComrakOptions::default());
+
+    let mut samples: Vec<Sample> = vec![];
+
+    /// `adjacent` is whether the last sample is the previous block, with only paragraphs between
+    /// them, so that an output block belongs to it
+    fn iter_nodes<'a>(
+        node: &'a AstNode<'a>,
+        samples: &mut Vec<Sample>,
+        adjacent: &mut bool,
+        token: &Option<String>,
+    ) {
+        match &mut node.data.borrow_mut().value {
+            NodeValue::CodeBlock(ref code) => {
+                if let Ok(code_block) = String::from_utf8(code.literal.clone()) {
+                    let info = String::from_utf8(code.info.clone()).unwrap_or_default();
+                    let (language, attributes) = FenceAttributes::parse(&info);
+                    let guess = if language.is_none() {
+                        Some(detect::guess(&code_block))
+                    } else {
+                        None
+                    };
+                    let code = Code {
+                        code: code_block,
+                        gist_id: None,
+                        language,
+                        options: LinkOptions::default(),
+                        expected: None,
+                        reported: None,
+                        attributes,
+                        guess,
+                        manifest: None,
+                        files: vec![],
+                    };
+                    if code.is_output() && *adjacent {
+                        if let Some(&mut Sample::Block(ref mut previous)) = samples.last_mut() {
+                            if previous.is_rust() {
+                                if previous.expected.is_none() {
+                                    previous.expected = Some(code.code);
+                                    return;
+                                } else if previous.reported.is_none() {
+                                    previous.reported = Some(code.code);
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    samples.push(Sample::Block(code));
+                    *adjacent = true;
+                }
+            }
+            NodeValue::Link(ref link) => {
+                if let Ok(link) = String::from_utf8(link.url.clone()) {
+                    if let Some(code) = linked_code(&link, token) {
+                        samples.push(Sample::Link(code));
+                    }
+                }
+            }
+            NodeValue::Text(ref mut text) => {
+                let finder = LinkFinder::new();
+                let text = String::from_utf8(text.to_vec()).unwrap();
+                finder.links(&text).for_each(|link| {
+                    if let Some(code) = linked_code(link.as_str(), token) {
+                        samples.push(Sample::Link(code));
+                    }
+                });
+            }
+            value => {
+                match *value {
+                    NodeValue::Document | NodeValue::Paragraph => (),
+                    ref other if other.block() => *adjacent = false,
+                    _ => (),
+                }
+                for c in node.children() {
+                    iter_nodes(c, samples, adjacent, token);
+                }
+            }
+        }
+    }
+    iter_nodes(root, &mut samples, &mut false, &token);
+
+    associate_manifests(&mut samples);
+
+    let code_blocks = samples.into_iter().map(Sample::fetch).collect::<Vec<_>>();
+    Box::new(
+        future::join_all(code_blocks)
+            .map(|code_blocks| code_blocks.into_iter().filter_map(|code| code).collect()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn samples(doc: &str) -> Vec<Code> {
+        get_code_samples(doc, &None).wait().unwrap()
+    }
+
+    #[test]
+    fn outputs_go_with_the_sample_before_them() {
+        let samples = samples(
+            "```rust\nfn main() {}\n```\n\nExpected:\n\n```text\nfoo\n```\n\n\
+             Instead:\n\n```text\nbar\n```\n",
+        );
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].expected, Some("foo\n".to_string()));
+        assert_eq!(samples[0].reported, Some("bar\n".to_string()));
+    }
+
+    #[test]
+    fn outputs_after_other_blocks_are_not_paired() {
+        let samples = samples(
+            "```rust\nfn main() {}\n```\n\n## Other\n\n```text\nfoo\n```\n\n\
+             - a list\n\n```text\nbar\n```\n",
+        );
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].expected, None);
+    }
+
+    fn gist(files: &[(&str, Option<&str>)]) -> github::gist::Gist {
+        let files = files
+            .iter()
+            .map(|&(name, language)| {
+                (
+                    name.to_string(),
+                    json!({ "filename": name, "content": "fn main() {}\n", "language": language }),
+                )
+            }).collect::<serde_json::Map<_, _>>();
+        serde_json::from_value(json!({
+            "url": "https://api.github.com/gists/0123",
+            "id": "0123",
+            "node_id": "gist",
+            "html_url": "https://gist.github.com/0123",
+            "files": files,
+        })).unwrap()
+    }
+
+    #[test]
+    fn gist_files_are_rust_by_extension() {
+        let rust = |files: &[(&str, Option<&str>)]| {
+            gist_sample(gist(files), LinkOptions::default())
+                .unwrap()
+                .is_rust()
+        };
+        assert!(rust(&[("main.rs", None)]));
+        assert!(rust(&[("sample.rs", Some("Rust"))]));
+        assert!(!rust(&[("notes", None)]));
+        assert!(!rust(&[("notes.txt", Some("Rust"))]));
+        assert!(!rust(&[("script.py", Some("Python"))]));
+    }
+
+    #[test]
+    fn fence_attributes() {
+        let none = FenceAttributes::default();
+        let rust = Some("rust");
+        let cases = vec![
+            ("", None, none),
+            ("rust", rust, none),
+            ("text", Some("text"), none),
+            (
+                "rust,ignore",
+                rust,
+                FenceAttributes {
+                    ignore: true,
+                    ..none
+                },
+            ),
+            (
+                "ignore-windows",
+                rust,
+                FenceAttributes {
+                    ignore: true,
+                    ..none
+                },
+            ),
+            (
+                "no_run",
+                rust,
+                FenceAttributes {
+                    no_run: true,
+                    ..none
+                },
+            ),
+            (
+                "should_panic",
+                rust,
+                FenceAttributes {
+                    should_panic: true,
+                    ..none
+                },
+            ),
+            (
+                "compile_fail,E0308",
+                rust,
+                FenceAttributes {
+                    compile_fail: true,
+                    ..none
+                },
+            ),
+            (
+                "rust,edition2018",
+                rust,
+                FenceAttributes {
+                    edition: Some(playground::Edition::Edition2018),
+                    ..none
+                },
+            ),
+            ("edition2030", rust, none),
+            ("rust,unknown", rust, none),
+            (
+                "unknown,no_run",
+                Some("unknown"),
+                FenceAttributes {
+                    no_run: true,
+                    ..none
+                },
+            ),
+            (
+                "rust , no_run ,should_panic",
+                rust,
+                FenceAttributes {
+                    no_run: true,
+                    should_panic: true,
+                    ..none
+                },
+            ),
+        ];
+        for (info, language, expected) in cases {
+            let (parsed_language, parsed) = FenceAttributes::parse(info);
+            assert_eq!(parsed_language.as_ref().map(String::as_str), language);
+            assert_eq!(parsed, expected, "{}", info);
+        }
+    }
+
+    #[test]
+    fn playground_links() {
+        let shared = samples(
+            "https://play.rust-lang.org/?version=nightly&mode=release&edition=2018\
+             &code=fn%20main()%20%7B%0A%20%20%20%20println!(%22hi%22)%3B%0A%7D",
+        );
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].code, "fn main() {\n    println!(\"hi\");\n}");
+        assert_eq!(
+            shared[0].options,
+            LinkOptions {
+                channel: Some(playground::Channel::Nightly),
+                mode: Some(playground::Mode::Release),
+                edition: Some(playground::Edition::Edition2018),
+            }
+        );
+        // what the link does not set is left to the request
+        let unset = samples("https://play.rust-lang.org/?code=fn%20main()%20%7B%7D");
+        assert_eq!(unset[0].options, LinkOptions::default());
+        // a link that cannot be run as it was shared is skipped
+        for link in &[
+            "https://play.rust-lang.org/?version=1.30.0&code=fn%20main()%20%7B%7D",
+            "https://play.rust-lang.org/?mode=profile&code=fn%20main()%20%7B%7D",
+            "https://play.rust-lang.org/?edition=2030&code=fn%20main()%20%7B%7D",
+        ] {
+            assert!(samples(link).is_empty(), "{}", link);
+        }
+        // links to the playground without code are not samples
+        assert!(samples("https://play.rust-lang.org/?version=nightly").is_empty());
+    }
+
+    #[test]
+    fn only_rust_files_and_manifests_are_followed() {
+        for link in &[
+            "https://github.com/owner/repo/blob/master/README.md",
+            "https://raw.githubusercontent.com/owner/repo/master/build.sh",
+        ] {
+            assert!(samples(link).is_empty(), "{}", link);
+        }
+    }
+}
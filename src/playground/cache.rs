@@ -0,0 +1,217 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use failure;
+use futures::future::{self, Future, Shared};
+use hex;
+use sha1::{Digest, Sha1};
+
+use super::{Action, Backend, Channel, Options, Project, Response, Version};
+
+/// Seconds during which the version of a channel is not asked again, a new nightly is used at
+/// most this long after the backend got it
+const VERSION_TTL: u64 = 600;
+
+/// Results of the least recently used samples are dropped beyond this number
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+struct Results {
+    capacity: usize,
+    /// Responses with the tick of their last use
+    by_key: HashMap<String, (Response, u64)>,
+    /// Keys by the tick of their last use, least recently used first
+    by_use: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Results {
+    fn get(&mut self, key: &str) -> Option<Response> {
+        let &mut (ref response, ref mut last_use) = self.by_key.get_mut(key)?;
+        self.tick += 1;
+        self.by_use.remove(last_use);
+        self.by_use.insert(self.tick, key.to_string());
+        *last_use = self.tick;
+        Some(response.clone())
+    }
+
+    fn insert(&mut self, key: String, response: Response) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((_, last_use)) = self.by_key.remove(&key) {
+            self.by_use.remove(&last_use);
+        }
+        self.tick += 1;
+        self.by_use.insert(self.tick, key.clone());
+        self.by_key.insert(key, (response, self.tick));
+        while self.by_key.len() > self.capacity {
+            let least_recent = match self.by_use.values().next() {
+                Some(least_recent) => least_recent.clone(),
+                None => break,
+            };
+            if let Some((_, last_use)) = self.by_key.remove(&least_recent) {
+                self.by_use.remove(&last_use);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref RESULTS: Mutex<Results> = {
+        Mutex::new(Results {
+            capacity: DEFAULT_CAPACITY,
+            by_key: HashMap::new(),
+            by_use: BTreeMap::new(),
+            tick: 0,
+        })
+    };
+}
+lazy_static! {
+    static ref VERSIONS: Mutex<HashMap<&'static str, (Version, Instant)>> =
+        { Mutex::new(HashMap::new()) };
+}
+
+type Pending = Shared<Box<Future<Item = Response, Error = failure::Error>>>;
+
+thread_local! {
+    /// Responses not received yet, shared by the copies of a sample checked at the same time
+    static PENDING: RefCell<HashMap<String, Pending>> = RefCell::new(HashMap::new());
+}
+
+/// Number of results kept, 0 to always ask the backend
+pub fn set_capacity(capacity: usize) {
+    let mut results = RESULTS.lock().unwrap();
+    results.capacity = capacity;
+    results.by_key.clear();
+    results.by_use.clear();
+}
+
+/// Forget everything learned from the previous backend
+pub fn clear() {
+    let mut results = RESULTS.lock().unwrap();
+    results.by_key.clear();
+    results.by_use.clear();
+    VERSIONS.lock().unwrap().clear();
+}
+
+/// Version of a channel, asked to the backend at most once every `VERSION_TTL`
+pub fn version(
+    backend: &Arc<Backend>,
+    channel: Channel,
+) -> Box<Future<Item = Version, Error = failure::Error>> {
+    if let Some(&(ref version, asked)) = VERSIONS.lock().unwrap().get(channel.name()) {
+        if asked.elapsed() < Duration::new(VERSION_TTL, 0) {
+            return Box::new(future::ok(version.clone()));
+        }
+    }
+    Box::new(backend.version(channel).map(move |version| {
+        VERSIONS
+            .lock()
+            .unwrap()
+            .insert(channel.name(), (version.clone(), Instant::now()));
+        version
+    }))
+}
+
+/// Response of the backend, reused if the same project was already sent with the same action
+/// and options to the same version of the compiler, or if it is being sent
+pub fn execute(
+    backend: Arc<Backend>,
+    project: Project,
+    action: Action,
+    options: Options,
+) -> Box<Future<Item = Response, Error = failure::Error>> {
+    if RESULTS.lock().unwrap().capacity == 0 {
+        return backend.execute(project, action, options);
+    }
+    let key = cache_key(&project, action, options);
+    let pending = PENDING.with(|pending| pending.borrow().get(&key).cloned());
+    let pending = match pending {
+        Some(pending) => {
+            debug!("waiting for the result of {:?} with {:?}", action, options);
+            pending
+        }
+        None => {
+            let pending = reuse_or_execute(backend, project, action, options, key.clone()).shared();
+            PENDING.with(|pending_by_key| {
+                pending_by_key.borrow_mut().insert(key, pending.clone());
+            });
+            pending
+        }
+    };
+    Box::new(
+        pending
+            .map(|response| (*response).clone())
+            .map_err(|err| failure::err_msg(err.to_string())),
+    )
+}
+
+fn reuse_or_execute(
+    backend: Arc<Backend>,
+    project: Project,
+    action: Action,
+    options: Options,
+    key: String,
+) -> Box<Future<Item = Response, Error = failure::Error>> {
+    let pending_key = key.clone();
+    Box::new(
+        version(&backend, options.channel)
+            .then(
+                move |version| -> Box<Future<Item = Response, Error = failure::Error>> {
+                    let version = match version {
+                        Ok(version) => version,
+                        Err(err) => {
+                            debug!(
+                                "not caching, no version for {}: {}",
+                                options.channel.name(),
+                                err
+                            );
+                            return backend.execute(project, action, options);
+                        }
+                    };
+                    let key = format!("{} {}", key, version.name());
+                    if let Some(response) = RESULTS.lock().unwrap().get(&key) {
+                        debug!("reusing result of {:?} with {:?}", action, options);
+                        return Box::new(future::ok(response));
+                    }
+                    Box::new(
+                        backend
+                            .execute(project, action, options)
+                            .map(move |response| {
+                                // a timeout may come from the load of the backend
+                                if !response.timed_out() {
+                                    RESULTS.lock().unwrap().insert(key, response.clone());
+                                }
+                                response
+                            }),
+                    )
+                },
+            ).then(move |response| {
+                PENDING.with(|pending| pending.borrow_mut().remove(&pending_key));
+                response
+            }),
+    )
+}
+
+/// Key of a project sent with an action and options, the version of the compiler being added
+/// once known. Trailing whitespace at the end of the code is the only difference ignored, as
+/// any other can change the meaning of a string literal or what rustfmt and Clippy say.
+fn cache_key(project: &Project, action: Action, options: Options) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input(project.code.trim_right().as_bytes());
+    hasher.input(format!("\0{:?}", project.crate_type).as_bytes());
+    for dependency in &project.dependencies {
+        hasher.input(format!("\0{} = {}", dependency.name, dependency.spec).as_bytes());
+    }
+    for file in &project.files {
+        hasher.input(format!("\0{}\0{}", file.name, file.content.trim_right()).as_bytes());
+    }
+    format!(
+        "{} {} {:?}",
+        hex::encode(hasher.result()),
+        action.name(),
+        options
+    )
+}
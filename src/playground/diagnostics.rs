@@ -0,0 +1,330 @@
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Span {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    /// Error code like `E0382`, or lint name like `clippy::needless_range_loop`
+    pub code: Option<String>,
+    pub message: String,
+    pub span: Option<Span>,
+    pub suggestion: Option<String>,
+}
+
+const SUMMARIES: &[&str] = &[
+    "aborting due to",
+    "Could not compile",
+    "could not compile",
+    "build failed",
+    "unused manifest key",
+];
+
+/// Parse the human readable output of rustc and Clippy. Each diagnostic ends with a blank line,
+/// and what follows cargo running the program is its own output.
+pub fn parse(stderr: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut current: Option<Diagnostic> = None;
+    for line in stderr.lines() {
+        if line.trim().is_empty() {
+            diagnostics.extend(current.take());
+            continue;
+        }
+        if line.trim_left().starts_with("Running `") {
+            break;
+        }
+        if let Some(diagnostic) = parse_header(line) {
+            diagnostics.extend(current.take());
+            if !is_summary(&diagnostic.message) {
+                current = Some(diagnostic);
+            }
+            continue;
+        }
+        if let Some(ref mut diagnostic) = current {
+            let line = line.trim_left();
+            if line.starts_with("--> ") {
+                if diagnostic.span.is_none() {
+                    diagnostic.span = parse_span(&line[4..]);
+                }
+            } else if let Some(lint) = lint_name(line) {
+                if diagnostic.code.is_none() {
+                    diagnostic.code = Some(lint);
+                }
+            } else if let Some(suggestion) = suggestion(line) {
+                if diagnostic.suggestion.is_none() {
+                    diagnostic.suggestion = Some(suggestion);
+                }
+            }
+        }
+    }
+    diagnostics.extend(current);
+    diagnostics
+}
+
+fn parse_header(line: &str) -> Option<Diagnostic> {
+    let (level, rest) = if line.starts_with("error") {
+        (Level::Error, &line[5..])
+    } else if line.starts_with("warning") {
+        (Level::Warning, &line[7..])
+    } else {
+        return None;
+    };
+    let (code, rest) = if rest.starts_with('[') {
+        let end = rest.find(']')?;
+        (Some(rest[1..end].to_string()), &rest[end + 1..])
+    } else {
+        (None, rest)
+    };
+    if !rest.starts_with(": ") {
+        return None;
+    }
+    Some(Diagnostic {
+        level,
+        code,
+        message: rest[2..].trim().to_string(),
+        span: None,
+        suggestion: None,
+    })
+}
+
+fn is_summary(message: &str) -> bool {
+    SUMMARIES.iter().any(|summary| message.starts_with(summary))
+        || (message.starts_with('`') && message.contains(" generated "))
+}
+
+fn parse_span(location: &str) -> Option<Span> {
+    let mut parts = location.trim().rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    Some(Span {
+        file: parts.next()?.to_string(),
+        line,
+        column,
+    })
+}
+
+/// Lint name from notes like `#[warn(clippy::needless_range_loop)] on by default`, or from
+/// links to the Clippy lint list
+fn lint_name(line: &str) -> Option<String> {
+    if let Some(start) = line.find("#[") {
+        let attribute = &line[start + 2..];
+        let open = attribute.find('(')?;
+        let close = attribute.find(')')?;
+        return match &attribute[..open] {
+            "warn" | "deny" | "forbid" if open < close => {
+                Some(attribute[open + 1..close].to_string())
+            }
+            _ => None,
+        };
+    }
+    if line.contains("rust-clippy/") {
+        let anchor = line.find("index.html#")? + "index.html#".len();
+        return Some(format!("clippy::{}", line[anchor..].trim()));
+    }
+    None
+}
+
+fn suggestion(line: &str) -> Option<String> {
+    let line = if line.starts_with("= ") {
+        &line[2..]
+    } else {
+        line
+    };
+    if line.starts_with("help: ") && !line.contains("for further information") {
+        Some(line[6..].trim().to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: u32, column: u32) -> Option<Span> {
+        Some(Span {
+            file: "src/main.rs".to_string(),
+            line,
+            column,
+        })
+    }
+
+    #[test]
+    fn error_with_several_spans_and_notes() {
+        let stderr = r#"   Compiling playground v0.0.1 (file:///playground)
+error[E0382]: use of moved value: `v`
+ --> src/main.rs:4:22
+  |
+3 |     let w = v;
+  |         - value moved here
+4 |     println!("{:?}", v);
+  |                      ^ value used here after move
+  |
+  = note: move occurs because `v` has type `std::vec::Vec<i32>`, which does not implement the `Copy` trait
+
+error[E0308]: mismatched types
+  --> src/main.rs:10:5
+   |
+9  | fn answer() -> u32 {
+   |                --- expected `u32` because of return type
+10 |     "42"
+   |     ^^^^ expected u32, found reference
+   |
+   = note: expected type `u32`
+              found type `&'static str`
+  ::: src/lib.rs:1:1
+   |
+1  | pub fn f() {}
+   | ------------- other span
+
+error: aborting due to 2 previous errors
+
+Some errors occurred: E0308, E0382.
+For more information about an error, try `rustc --explain E0308`.
+error: Could not compile `playground`.
+
+To learn more, run the command again with --verbose.
+"#;
+        assert_eq!(
+            parse(stderr),
+            vec![
+                Diagnostic {
+                    level: Level::Error,
+                    code: Some("E0382".to_string()),
+                    message: "use of moved value: `v`".to_string(),
+                    span: span(4, 22),
+                    suggestion: None,
+                },
+                Diagnostic {
+                    level: Level::Error,
+                    code: Some("E0308".to_string()),
+                    message: "mismatched types".to_string(),
+                    span: span(10, 5),
+                    suggestion: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn warning_with_lint_note() {
+        let stderr = r#"   Compiling playground v0.0.1 (/playground)
+warning: unused variable: `x`
+ --> src/main.rs:2:9
+  |
+2 |     let x = 5;
+  |         ^ help: consider using `_x` instead
+  |
+  = note: #[warn(unused_variables)] on by default
+
+    Finished dev [unoptimized + debuginfo] target(s) in 0.62s
+"#;
+        assert_eq!(
+            parse(stderr),
+            vec![Diagnostic {
+                level: Level::Warning,
+                code: Some("unused_variables".to_string()),
+                message: "unused variable: `x`".to_string(),
+                span: span(2, 9),
+                suggestion: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn clippy_lint() {
+        let stderr = r#"    Checking playground v0.0.1 (/playground)
+warning: the loop variable `i` is only used to index `v`.
+ --> src/main.rs:3:14
+  |
+3 |     for i in 0..v.len() {
+  |              ^^^^^^^^^^
+  |
+  = note: #[warn(clippy::needless_range_loop)] on by default
+  = help: for further information visit https://rust-lang-nursery.github.io/rust-clippy/v0.0.212/index.html#needless_range_loop
+help: consider using an iterator
+  |
+3 |     for <item> in &v {
+  |         ^^^^^^    ^^
+
+warning: this expression borrows a reference that is immediately dereferenced by the compiler
+ --> src/main.rs:7:7
+  |
+7 |     f(&&x);
+  |       ^^^ help: change this to: `&x`
+  |
+  = help: for further information visit https://rust-lang-nursery.github.io/rust-clippy/v0.0.212/index.html#needless_borrow
+
+    Finished dev [unoptimized + debuginfo] target(s) in 0.41s
+"#;
+        assert_eq!(
+            parse(stderr),
+            vec![
+                Diagnostic {
+                    level: Level::Warning,
+                    code: Some("clippy::needless_range_loop".to_string()),
+                    message: "the loop variable `i` is only used to index `v`.".to_string(),
+                    span: span(3, 14),
+                    suggestion: Some("consider using an iterator".to_string()),
+                },
+                Diagnostic {
+                    level: Level::Warning,
+                    code: Some("clippy::needless_borrow".to_string()),
+                    message: "this expression borrows a reference that is immediately \
+                              dereferenced by the compiler"
+                        .to_string(),
+                    span: span(7, 7),
+                    suggestion: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn program_output_is_not_a_diagnostic() {
+        let stderr = r#"   Compiling playground v0.0.1 (/playground)
+warning: function is never used: `unused`
+ --> src/main.rs:1:1
+  |
+1 | fn unused() {}
+  | ^^^^^^^^^^^
+  |
+  = note: #[warn(dead_code)] on by default
+
+    Finished dev [unoptimized + debuginfo] target(s) in 0.58s
+     Running `target/debug/playground`
+error: not a compiler error
+  --> src/lib.rs:12:3
+warning: printed by the program
+thread 'main' panicked at 'oops --> src/main.rs:9:9', src/main.rs:5:5
+"#;
+        assert_eq!(
+            parse(stderr),
+            vec![Diagnostic {
+                level: Level::Warning,
+                code: Some("dead_code".to_string()),
+                message: "function is never used: `unused`".to_string(),
+                span: span(1, 1),
+                suggestion: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn arrow_after_a_diagnostic_ends() {
+        let stderr = "error: linking with `cc` failed: exit code: 1\n  |\n  = note: \"cc\" \"-m64\"\n\n  --> src/main.rs:3:3\n";
+        let diagnostics = parse(stderr);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, None);
+    }
+}
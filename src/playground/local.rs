@@ -0,0 +1,476 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure;
+use futures::future::Future;
+use futures::sync::oneshot;
+#[cfg(unix)]
+use libc;
+
+use super::{
+    Action, Backend, Channel, CrateType, Dependency, Edition, Mode, Options, Project, Response,
+    Version,
+};
+
+static CRATE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Time given to the readers of a killed command to see the end of its output
+const READER_GRACE: Duration = Duration::from_secs(5);
+
+static WORKERS: AtomicUsize = AtomicUsize::new(8);
+
+/// Set the number of threads building and running code samples, before the first one is run
+pub fn set_workers(workers: usize) {
+    WORKERS.store(workers.max(1), Ordering::SeqCst);
+}
+
+/// Work sent to the threads of the pool
+trait Job: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Job for F {
+    fn run(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+lazy_static! {
+    static ref POOL: Mutex<mpsc::Sender<Box<Job>>> = {
+        let (sender, receiver) = mpsc::channel::<Box<Job>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..WORKERS.load(Ordering::SeqCst) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("local-{}", index))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        // a panicking job drops its sender, which is reported as an interruption
+                        Ok(job) => {
+                            let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| job.run()));
+                        }
+                        Err(_) => break,
+                    }
+                }).expect("could not start the local workers");
+        }
+        Mutex::new(sender)
+    };
+}
+
+/// Run `job` on the pool, waiting for a free thread
+fn spawn<T, F>(job: F) -> oneshot::Receiver<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let job = move || {
+        let _ = sender.send(job());
+    };
+    let _ = POOL.lock().unwrap().send(Box::new(job));
+    receiver
+}
+
+/// Build and run code samples on this machine with cargo and rustup toolchains, on a pool of
+/// `set_workers` threads. The code is not sandboxed: it runs with the rights of this process.
+#[derive(Debug, Clone)]
+pub struct Local {
+    /// Rustup toolchain to use instead of the requested channel
+    pub toolchain: Option<String>,
+    /// Time after which cargo is killed
+    pub timeout: Duration,
+    /// Maximum number of bytes kept from stdout and from stderr
+    pub output_limit: usize,
+    /// Directory of crates vendored with `cargo vendor`, used instead of crates.io to resolve
+    /// dependencies
+    pub vendor: Option<PathBuf>,
+}
+
+impl Default for Local {
+    fn default() -> Self {
+        Local {
+            toolchain: None,
+            timeout: Duration::new(30, 0),
+            output_limit: 100_000,
+            vendor: None,
+        }
+    }
+}
+
+impl Backend for Local {
+    fn execute(
+        &self,
+        project: Project,
+        action: Action,
+        options: Options,
+    ) -> Box<Future<Item = Response, Error = failure::Error>> {
+        debug!("running locally {:?} with {:?}", action, options);
+        let local = self.clone();
+        let receiver = spawn(move || local.run(&project, action, options));
+        Box::new(receiver.then(|result| match result {
+            Ok(response) => response,
+            Err(_) => Err(failure::err_msg("local execution was interrupted")),
+        }))
+    }
+
+    fn version(&self, channel: Channel) -> Box<Future<Item = Version, Error = failure::Error>> {
+        let toolchain = self.channel_toolchain(channel);
+        let receiver = spawn(move || rustc_version(&toolchain));
+        Box::new(receiver.then(|result| match result {
+            Ok(version) => version,
+            Err(_) => Err(failure::err_msg("local version query was interrupted")),
+        }))
+    }
+
+    fn toolchain(&self) -> Option<String> {
+        self.toolchain.clone()
+    }
+
+    fn inlines_modules(&self) -> bool {
+        false
+    }
+}
+
+impl Local {
+    fn channel_toolchain(&self, channel: Channel) -> String {
+        self.toolchain
+            .clone()
+            .unwrap_or_else(|| channel.name().to_string())
+    }
+
+    fn run(
+        &self,
+        project: &Project,
+        action: Action,
+        options: Options,
+    ) -> Result<Response, failure::Error> {
+        let missing = self.missing_dependencies(&project.dependencies);
+        if !missing.is_empty() {
+            return Ok(Response::unavailable(&missing, "in the vendored registry"));
+        }
+        let dir = env::temp_dir().join(format!(
+            "beast-glatisant-{}-{}",
+            process::id(),
+            CRATE_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let result = self
+            .create_crate(&dir, project, options)
+            .map_err(failure::Error::from)
+            .and_then(|source| self.cargo(&dir, &source, project.crate_type, action, options));
+        if let Err(err) = fs::remove_dir_all(&dir) {
+            warn!("could not remove {}: {}", dir.display(), err);
+        }
+        result
+    }
+
+    /// Dependencies absent from the vendor directory, whose entries are named after the crate
+    /// and possibly its version
+    fn missing_dependencies<'a>(&self, dependencies: &'a [Dependency]) -> Vec<&'a str> {
+        let vendor = match self.vendor {
+            Some(ref vendor) => vendor,
+            None => return vec![],
+        };
+        let vendored = fs::read_dir(vendor)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .collect::<Vec<_>>()
+            }).unwrap_or_default();
+        dependencies
+            .iter()
+            .map(|dependency| dependency.name.as_str())
+            .filter(|name| {
+                !vendored.iter().any(|entry| {
+                    entry == name
+                        || (entry.starts_with(name)
+                            && entry[name.len()..].starts_with('-')
+                            && entry[name.len() + 1..]
+                                .chars()
+                                .next()
+                                .map(|c| c.is_ascii_digit())
+                                .unwrap_or(false))
+                })
+            }).collect()
+    }
+
+    fn create_crate(&self, dir: &Path, project: &Project, options: Options) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir.join("src"))?;
+        let mut manifest =
+            "[package]\nname = \"playground\"\nversion = \"0.0.1\"\nauthors = []\n".to_string();
+        match options.edition {
+            Some(Edition::Edition2015) => manifest.push_str("edition = \"2015\"\n"),
+            Some(Edition::Edition2018) => manifest.push_str("edition = \"2018\"\n"),
+            Some(Edition::Edition2021) => manifest.push_str("edition = \"2021\"\n"),
+            Some(Edition::Edition2024) => manifest.push_str("edition = \"2024\"\n"),
+            None => (),
+        }
+        if project.crate_type == CrateType::ProcMacro {
+            manifest.push_str("\n[lib]\nproc-macro = true\n");
+        }
+        if !project.dependencies.is_empty() {
+            manifest.push_str("\n[dependencies]\n");
+            for dependency in &project.dependencies {
+                manifest.push_str(&format!("{} = {}\n", dependency.name, dependency.spec));
+            }
+        }
+        fs::write(dir.join("Cargo.toml"), manifest)?;
+        if let Some(ref vendor) = self.vendor {
+            fs::create_dir_all(dir.join(".cargo"))?;
+            fs::write(
+                dir.join(".cargo").join("config"),
+                format!(
+                    "[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n\
+                     [source.vendored-sources]\ndirectory = {:?}\n",
+                    vendor.display().to_string()
+                ),
+            )?;
+        }
+        for file in &project.files {
+            // names come from gists, keep them inside the crate, Rust files in `src` and others
+            // next to the manifest, which they cannot replace
+            let name = match Path::new(&file.name).file_name() {
+                Some(name) => name,
+                None => continue,
+            };
+            if file.name.ends_with(".rs") {
+                fs::write(dir.join("src").join(name), &file.content)?;
+            } else if name != "Cargo.toml" {
+                fs::write(dir.join(name), &file.content)?;
+            }
+        }
+        let source = dir.join("src").join(match project.crate_type {
+            CrateType::Bin => "main.rs",
+            CrateType::Lib | CrateType::ProcMacro => "lib.rs",
+        });
+        fs::write(&source, &project.code)?;
+        Ok(source)
+    }
+
+    fn cargo(
+        &self,
+        dir: &Path,
+        source: &Path,
+        crate_type: CrateType,
+        action: Action,
+        options: Options,
+    ) -> Result<Response, failure::Error> {
+        let toolchain = self.channel_toolchain(options.channel);
+        let profile = match options.mode {
+            Mode::Debug => "debug",
+            Mode::Release => "release",
+        };
+        let mut command = Command::new("cargo");
+        command.arg(format!("+{}", toolchain)).current_dir(dir);
+        match action {
+            Action::Run | Action::Build => command.arg("build"),
+            Action::Test => command.args(&["test", "--no-run"]),
+            Action::Format => command.arg("fmt"),
+            Action::Clippy => command.arg("clippy"),
+        };
+        if options.mode == Mode::Release && action != Action::Format {
+            command.arg("--release");
+        }
+        let build = self.execute(command)?;
+        if build.success != Some(true) {
+            return Ok(build.into_response(None));
+        }
+        let executable = match action {
+            // a library has nothing to run once built
+            Action::Run if crate_type != CrateType::Bin => return Ok(build.into_response(None)),
+            Action::Build => return Ok(build.into_response(None)),
+            Action::Run => Some(dir.join("target").join(profile).join("playground")),
+            Action::Test => find_test_executable(&dir.join("target").join(profile).join("deps"))?,
+            Action::Format => {
+                return Ok(build.into_response(Some(fs::read_to_string(source)?)));
+            }
+            Action::Clippy => return Ok(build.into_response(None)),
+        };
+        match executable {
+            Some(executable) => {
+                let mut command = Command::new(executable);
+                command.current_dir(dir);
+                let run = self.execute(command)?;
+                Ok(Output {
+                    success: run.success,
+                    stdout: run.stdout,
+                    stderr: build.stderr + &run.stderr,
+                }.into_response(None))
+            }
+            None => Err(failure::err_msg("could not find the test executable")),
+        }
+    }
+
+    /// Run a command with the configured timeout and output limit
+    fn execute(&self, mut command: Command) -> io::Result<Output> {
+        in_own_process_group(&mut command);
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = read_limited(child.stdout.take(), self.output_limit);
+        let stderr = read_limited(child.stderr.take(), self.output_limit);
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Output {
+                    success: Some(status.success()),
+                    stdout: stdout.recv().unwrap_or_default(),
+                    stderr: stderr.recv().unwrap_or_default(),
+                });
+            }
+            if start.elapsed() > self.timeout {
+                kill(&mut child)?;
+                child.wait()?;
+                // the pipes are closed once every process of the group is dead, unless one of
+                // them left it
+                let stdout = stdout.recv_timeout(READER_GRACE);
+                let stderr = stderr.recv_timeout(READER_GRACE);
+                if stdout.is_err() || stderr.is_err() {
+                    warn!("the output of a killed command is still open, its readers are left");
+                }
+                return Ok(Output {
+                    success: None,
+                    stdout: stdout.unwrap_or_default(),
+                    stderr: format!(
+                        "{}killed after {} seconds\n",
+                        stderr.unwrap_or_default(),
+                        self.timeout.as_secs()
+                    ),
+                });
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// Start the command in a process group of its own, so that the processes it starts are killed
+/// with it
+#[cfg(unix)]
+fn in_own_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.before_exec(|| {
+        if unsafe { libc::setpgid(0, 0) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn in_own_process_group(_command: &mut Command) {}
+
+#[cfg(unix)]
+fn kill(child: &mut Child) -> io::Result<()> {
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } == 0 {
+        Ok(())
+    } else {
+        child.kill()
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+struct Output {
+    /// `None` if the command was killed
+    success: Option<bool>,
+    stdout: String,
+    stderr: String,
+}
+
+impl Output {
+    fn into_response(self, code: Option<String>) -> Response {
+        Response {
+            success: self.success.unwrap_or(false),
+            stdout: self.stdout,
+            stderr: self.stderr,
+            code,
+            killed: self.success.is_none(),
+        }
+    }
+}
+
+/// Version from the output of `rustc -vV`
+fn rustc_version(toolchain: &str) -> Result<Version, failure::Error> {
+    let output = Command::new("rustc")
+        .arg(format!("+{}", toolchain))
+        .arg("-vV")
+        .output()?;
+    if !output.status.success() {
+        return Err(failure::err_msg(format!(
+            "could not get the version of {}: {}",
+            toolchain,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| {
+        output
+            .lines()
+            .find(|line| line.starts_with(name))
+            .map(|line| line[name.len()..].trim().to_string())
+            .unwrap_or_default()
+    };
+    Ok(Version {
+        version: field("release:"),
+        hash: field("commit-hash:"),
+        date: field("commit-date:"),
+    })
+}
+
+fn find_test_executable(deps: &Path) -> io::Result<Option<PathBuf>> {
+    for entry in fs::read_dir(deps)? {
+        let path = entry?.path();
+        let is_test_executable = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.starts_with("playground-") && !name.contains('.'))
+            .unwrap_or(false);
+        if is_test_executable {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+fn read_limited<R>(reader: Option<R>, limit: usize) -> mpsc::Receiver<String>
+where
+    R: Read + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut kept = vec![];
+        let mut truncated = false;
+        if let Some(mut reader) = reader {
+            let mut buffer = [0; 4096];
+            while let Ok(read) = reader.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                let room = limit.saturating_sub(kept.len());
+                truncated |= read > room;
+                kept.extend_from_slice(&buffer[..read.min(room)]);
+            }
+        }
+        let mut output = String::from_utf8_lossy(&kept).into_owned();
+        if truncated {
+            output.push_str("\n[output truncated]\n");
+        }
+        let _ = sender.send(output);
+    });
+    receiver
+}
@@ -0,0 +1,174 @@
+use toml::Value;
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Dependency {
+    pub name: String,
+    /// TOML value of the dependency, like `"1.0"` or `{ version = "1.0", features = ["derive"] }`
+    pub spec: String,
+}
+
+/// Dependencies declared in a `Cargo.toml`, either in the `[dependencies]` table or in
+/// `[dependencies.name]` tables. `[dev-dependencies]`, `[build-dependencies]` and
+/// target-specific tables are ignored, with a warning.
+pub fn dependencies(manifest: &str) -> Vec<Dependency> {
+    let manifest = match manifest.parse::<Value>() {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            warn!("ignoring a manifest that is not valid TOML: {}", err);
+            return vec![];
+        }
+    };
+    for table in ignored_tables(&manifest) {
+        warn!(
+            "ignoring [{}] in a manifest, only [dependencies] are used",
+            table
+        );
+    }
+    manifest
+        .get("dependencies")
+        .and_then(Value::as_table)
+        .map(|dependencies| {
+            dependencies
+                .iter()
+                .map(|(name, spec)| Dependency {
+                    name: name.clone(),
+                    spec: inline(spec),
+                }).collect()
+        }).unwrap_or_default()
+}
+
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Other tables of dependencies, like `[dev-dependencies]` or
+/// `[target.'cfg(unix)'.dependencies]`
+fn ignored_tables(manifest: &Value) -> Vec<String> {
+    let mut tables = DEPENDENCY_TABLES[1..]
+        .iter()
+        .filter(|table| manifest.get(table).is_some())
+        .map(|table| table.to_string())
+        .collect::<Vec<_>>();
+    if let Some(targets) = manifest.get("target").and_then(Value::as_table) {
+        for (target, value) in targets {
+            for table in DEPENDENCY_TABLES {
+                if value.get(table).is_some() {
+                    tables.push(format!("target.{}.{}", key(target), table));
+                }
+            }
+        }
+    }
+    tables
+}
+
+/// A TOML value on a single line, tables being written inline
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Array(values) => format!(
+            "[{}]",
+            values.iter().map(inline).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Table(ref table) if table.is_empty() => "{}".to_string(),
+        Value::Table(table) => format!(
+            "{{ {} }}",
+            table
+                .iter()
+                .map(|(name, value)| format!("{} = {}", key(name), inline(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        value => value.to_string(),
+    }
+}
+
+/// A key, quoted unless it is a bare key
+fn key(name: &str) -> String {
+    let is_bare = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_bare {
+        name.to_string()
+    } else {
+        Value::String(name.to_string()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(manifest: &str) -> Vec<(String, String)> {
+        dependencies(manifest)
+            .into_iter()
+            .map(|dependency| (dependency.name, dependency.spec))
+            .collect()
+    }
+
+    fn expected(specs: &[(&str, &str)]) -> Vec<(String, String)> {
+        specs
+            .iter()
+            .map(|&(name, spec)| (name.to_string(), spec.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn dependency_specs() {
+        let cases = vec![
+            (
+                "[dependencies]\nserde = \"1.0\"\n",
+                vec![("serde", "\"1.0\"")],
+            ),
+            (
+                "[dependencies]\nserde = { version = \"1.0\", features = [\"derive\"] }\n",
+                vec![("serde", "{ features = [\"derive\"], version = \"1.0\" }")],
+            ),
+            (
+                "[dependencies.serde]\nversion = \"1.0\"\ndefault-features = false\n",
+                vec![("serde", "{ default-features = false, version = \"1.0\" }")],
+            ),
+            (
+                "[dependencies]\nrand = \"0.5\"\n[dependencies.serde]\n",
+                vec![("rand", "\"0.5\""), ("serde", "{}")],
+            ),
+            (
+                "[dependencies]\ntokio = { version = \"0.1\", features = [\n    \"io\",\n    \
+                 \"rt-full\", # the runtime\n] }\n",
+                vec![(
+                    "tokio",
+                    "{ features = [\"io\", \"rt-full\"], version = \"0.1\" }",
+                )],
+            ),
+            (
+                "[dependencies]\nfoo = { git = \"https://example.com/foo#main\" } # fork\n",
+                vec![("foo", "{ git = \"https://example.com/foo#main\" }")],
+            ),
+            (
+                "[package]\nname = \"sample\"\n\n[dependencies]\nlog = \"0.4\"\n\n\
+                 [dev-dependencies]\nrand = \"0.5\"\n\n[build-dependencies]\ncc = \"1.0\"\n\n\
+                 [target.'cfg(unix)'.dependencies]\nlibc = \"0.2\"\n",
+                vec![("log", "\"0.4\"")],
+            ),
+            ("[package]\nname = \"sample\"\n", vec![]),
+            ("[dependencies\nserde = \"1.0\"\n", vec![]),
+        ];
+        for (manifest, dependencies) in cases {
+            assert_eq!(specs(manifest), expected(&dependencies), "{}", manifest);
+        }
+    }
+
+    #[test]
+    fn ignored_dependency_tables() {
+        let manifest = "[dependencies]\nlog = \"0.4\"\n[dev-dependencies]\nrand = \"0.5\"\n\
+                        [build-dependencies]\ncc = \"1.0\"\n\
+                        [target.'cfg(unix)'.dependencies]\nlibc = \"0.2\"\n\
+                        [target.x86_64-pc-windows-msvc.dev-dependencies]\nwinapi = \"0.3\"\n";
+        assert_eq!(
+            ignored_tables(&manifest.parse().unwrap()),
+            vec![
+                "dev-dependencies",
+                "build-dependencies",
+                "target.\"cfg(unix)\".dependencies",
+                "target.x86_64-pc-windows-msvc.dev-dependencies",
+            ]
+        );
+    }
+}
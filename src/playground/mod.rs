@@ -0,0 +1,909 @@
+use actix_web::{client, HttpMessage};
+use failure;
+use futures::future::{self, Future};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use markdown;
+
+pub mod cache;
+pub mod diagnostics;
+pub mod local;
+pub mod manifest;
+pub mod scheduler;
+pub mod wrap;
+
+pub use self::local::Local;
+pub use self::manifest::Dependency;
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    Run,
+    /// Compile without running, for samples not meant to be run
+    Build,
+    Test,
+    Format,
+    Clippy,
+}
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Run => "run",
+            Action::Build => "build",
+            Action::Test => "test",
+            Action::Format => "format",
+            Action::Clippy => "clippy",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Channel {
+    Stable,
+    Beta,
+    Nightly,
+}
+impl Channel {
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+        }
+    }
+}
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(channel: &str) -> Result<Self, Self::Err> {
+        match channel {
+            "stable" => Ok(Channel::Stable),
+            "beta" => Ok(Channel::Beta),
+            "nightly" => Ok(Channel::Nightly),
+            _ => Err(format!("unknown channel {}", channel)),
+        }
+    }
+}
+impl Default for Channel {
+    fn default() -> Self {
+        Channel::Stable
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Mode {
+    Debug,
+    Release,
+}
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "debug" => Ok(Mode::Debug),
+            "release" => Ok(Mode::Release),
+            _ => Err(format!("unknown mode {}", mode)),
+        }
+    }
+}
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Debug
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Edition {
+    #[serde(rename = "2015")]
+    Edition2015,
+    #[serde(rename = "2018")]
+    Edition2018,
+    #[serde(rename = "2021")]
+    Edition2021,
+    #[serde(rename = "2024")]
+    Edition2024,
+}
+impl FromStr for Edition {
+    type Err = String;
+
+    fn from_str(edition: &str) -> Result<Self, Self::Err> {
+        match edition {
+            "2015" => Ok(Edition::Edition2015),
+            "2018" => Ok(Edition::Edition2018),
+            "2021" => Ok(Edition::Edition2021),
+            "2024" => Ok(Edition::Edition2024),
+            _ => Err(format!("unknown edition {}", edition)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum CrateType {
+    Bin,
+    Lib,
+    #[serde(rename = "proc-macro")]
+    ProcMacro,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(default)]
+pub struct Options {
+    pub channel: Channel,
+    pub mode: Mode,
+    pub edition: Option<Edition>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub content: String,
+}
+
+/// A crate built from a code sample
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub code: String,
+    pub crate_type: CrateType,
+    /// Where the lines of `code` come from in the sample
+    pub lines: wrap::LineMap,
+    pub dependencies: Vec<Dependency>,
+    /// Other source files, next to the main one
+    pub files: Vec<SourceFile>,
+}
+
+/// Where a line of the code with its modules inlined comes from
+#[derive(Debug, PartialEq, Clone)]
+struct Origin {
+    /// Name of the other source file, none for the main one
+    file: Option<String>,
+    line: u32,
+}
+
+impl Project {
+    pub fn from_source(code: &str) -> Self {
+        let wrapped = wrap::wrap(code);
+        Project {
+            code: wrapped.code,
+            crate_type: wrapped.crate_type,
+            lines: wrapped.lines,
+            dependencies: vec![],
+            files: vec![],
+        }
+    }
+
+    /// The sample with the dependencies of the `Cargo.toml` given with it
+    pub fn from_code(code: &markdown::Code) -> Self {
+        Project {
+            dependencies: code
+                .manifest
+                .as_ref()
+                .map(|manifest| manifest::dependencies(manifest))
+                .unwrap_or_default(),
+            files: code.files.clone(),
+            ..Project::from_source(&code.code)
+        }
+    }
+
+    /// The main source with the modules declared in it replaced by the content of their file,
+    /// for backends taking a single file. A `lib.rs` next to the main file of a binary is added
+    /// as the `playground` module, the name of the library when the files are built by cargo.
+    pub fn inline_modules(&self) -> String {
+        self.inlined_lines()
+            .into_iter()
+            .map(|(line, _)| format!("{}\n", line))
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    /// Lines of `inline_modules` with where they come from, none for the lines added around
+    /// inlined modules
+    fn inlined_lines(&self) -> Vec<(String, Option<Origin>)> {
+        let mut lines = self.inline(None, &self.code, &mut vec![]);
+        let lib = match self.files.iter().find(|file| file.name == "lib.rs") {
+            Some(lib) if self.crate_type == CrateType::Bin => lib,
+            _ => return lines,
+        };
+        lines.retain(|&(ref line, _)| line.trim() != "extern crate playground;");
+        lines.push((String::new(), None));
+        lines.push(("pub mod playground {".to_string(), None));
+        lines.extend(self.inline(Some(lib), &lib.content, &mut vec!["lib".to_string()]));
+        lines.push(("}".to_string(), None));
+        lines
+    }
+
+    /// `code` with its modules inlined, and their own modules, except the modules it is part of.
+    /// `file` is the file of `code`, none for the main source.
+    fn inline(
+        &self,
+        file: Option<&SourceFile>,
+        code: &str,
+        parents: &mut Vec<String>,
+    ) -> Vec<(String, Option<Origin>)> {
+        let mut lines = vec![];
+        for (i, line) in code.lines().enumerate() {
+            let origin = Some(Origin {
+                file: file.map(|file| file.name.clone()),
+                line: i as u32 + 1,
+            });
+            match self.module_file(line, parents) {
+                Some((visibility, module, module_file)) => {
+                    lines.push((format!("{}mod {} {{", visibility, module), origin));
+                    parents.push(module.to_string());
+                    let content = self.inline(Some(module_file), &module_file.content, parents);
+                    parents.pop();
+                    lines.extend(trim_end(content));
+                    lines.push(("}".to_string(), None));
+                }
+                None => lines.push((line.to_string(), origin)),
+            }
+        }
+        lines
+    }
+
+    /// Visibility, name and file of the module declared by a line like `pub mod foo;`, if it is
+    /// one of the files and not one of the modules the line is part of
+    fn module_file<'a>(
+        &'a self,
+        line: &'a str,
+        parents: &[String],
+    ) -> Option<(&'static str, &'a str, &'a SourceFile)> {
+        let declaration = line.trim();
+        let (visibility, module) = if declaration.starts_with("pub mod ") {
+            ("pub ", &declaration["pub mod ".len()..])
+        } else if declaration.starts_with("mod ") {
+            ("", &declaration["mod ".len()..])
+        } else {
+            return None;
+        };
+        if !module.ends_with(';') {
+            return None;
+        }
+        let module = module.trim_right_matches(';').trim();
+        if parents.iter().any(|parent| parent == module) {
+            return None;
+        }
+        let file_name = format!("{}.rs", module);
+        self.files
+            .iter()
+            .find(|file| file.name == file_name)
+            .map(|file| (visibility, module, file))
+    }
+}
+
+/// Lines without the blank lines at their end
+fn trim_end(mut lines: Vec<(String, Option<Origin>)>) -> Vec<(String, Option<Origin>)> {
+    while lines
+        .last()
+        .map(|&(ref line, _)| line.trim().is_empty())
+        .unwrap_or(false)
+    {
+        lines.pop();
+    }
+    if let Some(&mut (ref mut line, _)) = lines.last_mut() {
+        let trimmed = line.trim_right().len();
+        line.truncate(trimmed);
+    }
+    lines
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Query {
+    code: String,
+    channel: Channel,
+    mode: Mode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edition: Option<Edition>,
+    crate_type: CrateType,
+    #[serde(rename = "tests")]
+    test: bool,
+    /// Output of the `compile` endpoint, MIR being the cheapest one that type-checks the code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<&'static str>,
+}
+impl Query {
+    fn from(action: Action, project: Project, options: Options) -> Self {
+        Query {
+            code: project.inline_modules(),
+            channel: options.channel,
+            mode: options.mode,
+            edition: options.edition,
+            test: action == Action::Test,
+            crate_type: project.crate_type,
+            target: if action == Action::Build {
+                Some("mir")
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Response {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+    pub code: Option<String>,
+    /// Whether the build or the run was killed by the backend for taking too long
+    #[serde(skip)]
+    pub killed: bool,
+}
+impl Response {
+    /// Failure for dependencies that the backend cannot provide
+    pub fn unavailable(dependencies: &[&str], place: &str) -> Self {
+        Response {
+            success: false,
+            stdout: String::new(),
+            stderr: format!(
+                "dependencies not available {}: {}\n",
+                place,
+                dependencies.join(", ")
+            ),
+            code: None,
+            killed: false,
+        }
+    }
+
+    /// Whether the sample was stopped for taking too long, which may not happen again under
+    /// less load
+    pub fn timed_out(&self) -> bool {
+        self.killed || self.stderr.contains("SIGKILL")
+    }
+}
+
+/// Version of the compiler behind a channel
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Version {
+    /// Like `1.31.0-nightly`
+    pub version: String,
+    pub hash: String,
+    pub date: String,
+}
+impl Version {
+    /// Like `1.31.0-nightly (4efdc04a5 2018-10-06)`
+    pub fn name(&self) -> String {
+        let short_hash = self.hash.get(..9).unwrap_or(&self.hash);
+        format!("{} ({} {})", self.version, short_hash, self.date)
+    }
+}
+
+/// Something able to build and run code samples
+pub trait Backend: Send + Sync {
+    fn execute(
+        &self,
+        project: Project,
+        action: Action,
+        options: Options,
+    ) -> Box<Future<Item = Response, Error = failure::Error>>;
+
+    /// Version of the compiler used for a channel
+    fn version(&self, channel: Channel) -> Box<Future<Item = Version, Error = failure::Error>>;
+
+    /// Toolchain used whatever the requested channel, if any
+    fn toolchain(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the other files of a project are inlined in its main file, rather than built as
+    /// files of their own
+    fn inlines_modules(&self) -> bool {
+        true
+    }
+}
+
+pub const DEFAULT_PLAYGROUND_URL: &str = "https://play.rust-lang.org";
+
+lazy_static! {
+    static ref PLAYGROUND_URL: RwLock<String> = { RwLock::new(DEFAULT_PLAYGROUND_URL.to_string()) };
+}
+
+/// Set the origin of the playground used to run code and to recognise shared links
+pub fn set_playground_url(url: &str) {
+    *PLAYGROUND_URL.write().unwrap() = url.trim_right_matches('/').to_string();
+    *CRATES.lock().unwrap() = None;
+}
+
+pub fn playground_url() -> String {
+    PLAYGROUND_URL.read().unwrap().clone()
+}
+
+/// A rust-playground instance, by default the public one at play.rust-lang.org
+pub struct Remote;
+
+#[derive(Deserialize, Debug, Clone)]
+struct Crates {
+    crates: Vec<Crate>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Crate {
+    name: String,
+}
+
+/// Seconds during which the crates available on the playground are not asked again
+const CRATES_TTL: u64 = 600;
+
+lazy_static! {
+    static ref CRATES: Mutex<Option<(Crates, Instant)>> = { Mutex::new(None) };
+}
+
+/// Crates available on the playground, asked at most once every `CRATES_TTL`
+fn available_crates() -> Box<Future<Item = Crates, Error = failure::Error>> {
+    if let Some((ref crates, asked)) = *CRATES.lock().unwrap() {
+        if asked.elapsed() < Duration::new(CRATES_TTL, 0) {
+            return Box::new(future::ok(crates.clone()));
+        }
+    }
+    Box::new(
+        fetch(format!("{}/meta/crates", playground_url()), |url| {
+            client::get(url)
+                .timeout(Duration::new(30, 0))
+                .finish()
+                .unwrap()
+        }).map(|crates: Crates| {
+            *CRATES.lock().unwrap() = Some((crates.clone(), Instant::now()));
+            crates
+        }),
+    )
+}
+
+impl Backend for Remote {
+    fn execute(
+        &self,
+        project: Project,
+        action: Action,
+        options: Options,
+    ) -> Box<Future<Item = Response, Error = failure::Error>> {
+        debug!("calling playground for {:?} with {:?}", action, options);
+        if project.dependencies.is_empty() {
+            return Remote::send(project, action, options);
+        }
+        // only the most downloaded crates are available on the playground
+        Box::new(available_crates().and_then(move |available| {
+            Remote::send_if_available(&available, project, action, options)
+        }))
+    }
+
+    fn version(&self, channel: Channel) -> Box<Future<Item = Version, Error = failure::Error>> {
+        fetch(
+            format!("{}/meta/version/{}", playground_url(), channel.name()),
+            |url| {
+                client::get(url)
+                    .timeout(Duration::new(30, 0))
+                    .finish()
+                    .unwrap()
+            },
+        )
+    }
+}
+
+impl Remote {
+    fn send_if_available(
+        available: &Crates,
+        project: Project,
+        action: Action,
+        options: Options,
+    ) -> Box<Future<Item = Response, Error = failure::Error>> {
+        let normalize = |name: &str| name.replace('-', "_");
+        let missing = project
+            .dependencies
+            .iter()
+            .map(|dependency| dependency.name.as_str())
+            .filter(|name| {
+                !available
+                    .crates
+                    .iter()
+                    .any(|krate| normalize(&krate.name) == normalize(name))
+            }).collect::<Vec<_>>();
+        if missing.is_empty() {
+            Remote::send(project, action, options)
+        } else {
+            Box::new(future::ok(Response::unavailable(
+                &missing,
+                "on the playground",
+            )))
+        }
+    }
+
+    fn send(
+        project: Project,
+        action: Action,
+        options: Options,
+    ) -> Box<Future<Item = Response, Error = failure::Error>> {
+        let url = format!(
+            "{}/{}",
+            playground_url(),
+            match action {
+                Action::Run => "execute",
+                Action::Build => "compile",
+                Action::Test => "execute",
+                Action::Clippy => "clippy",
+                Action::Format => "format",
+            }
+        );
+        let query = Query::from(action, project, options);
+        fetch(url, move |url| {
+            client::post(url)
+                .timeout(Duration::new(30, 0))
+                .json(&query)
+                .unwrap()
+        })
+    }
+}
+
+/// Host of the playground, requests to it share its rate limit
+fn playground_host() -> String {
+    let url = playground_url();
+    let without_scheme = url.splitn(2, "://").last().unwrap_or(&url);
+    without_scheme.split('/').next().unwrap_or("").to_string()
+}
+
+/// Send the request built by `request` to `url` through the scheduler, and parse the answer
+fn fetch<T, R>(url: String, request: R) -> Box<Future<Item = T, Error = failure::Error>>
+where
+    R: Fn(&str) -> client::ClientRequest + 'static,
+    for<'de> T: Deserialize<'de>,
+    T: 'static,
+{
+    scheduler::schedule(&playground_host(), move || {
+        let url = url.clone();
+        Box::new(
+            request(&url)
+                .send()
+                .map_err(|err| scheduler::Failure::Fatal(err.into()))
+                .and_then(move |resp| scheduler::check_status(&url, resp))
+                .and_then(|resp| {
+                    resp.json()
+                        .map_err(|err| scheduler::Failure::Fatal(err.into()))
+                }),
+        )
+    })
+}
+
+lazy_static! {
+    static ref BACKEND: RwLock<Arc<Backend>> = { RwLock::new(Arc::new(Remote)) };
+}
+
+/// Replace the backend used by `ask_playground`, the public playground by default
+pub fn set_backend<B: Backend + 'static>(backend: B) {
+    *BACKEND.write().unwrap() = Arc::new(backend);
+    cache::clear();
+}
+
+pub fn ask_playground(
+    code: &str,
+    action: Action,
+    options: Options,
+) -> impl Future<Item = Response, Error = failure::Error> {
+    ask_project(Project::from_source(code), action, options)
+}
+
+/// Run a code sample found in markdown, with its dependencies
+pub fn ask_sample(
+    code: &markdown::Code,
+    action: Action,
+    options: Options,
+) -> impl Future<Item = Response, Error = failure::Error> {
+    ask_project(Project::from_code(code), action, options)
+}
+
+/// Run a project with the current backend, once there is a free place among the projects in
+/// flight
+pub fn ask_project(
+    project: Project,
+    action: Action,
+    options: Options,
+) -> impl Future<Item = Response, Error = failure::Error> {
+    let backend = BACKEND.read().unwrap().clone();
+    scheduler::limit(move || cache::execute(backend, project, action, options))
+}
+
+pub fn ask_version(channel: Channel) -> impl Future<Item = Version, Error = failure::Error> {
+    let backend = BACKEND.read().unwrap().clone();
+    cache::version(&backend, channel)
+}
+
+pub fn ask_playground_simpl(
+    code: &str,
+    action: Action,
+    options: Options,
+) -> impl Future<Item = String, Error = failure::Error> {
+    ask_playground(code, action, options).map(move |playground| simplify(action, playground))
+}
+
+pub fn simplify(action: Action, playground: Response) -> String {
+    match (action, playground) {
+        (
+            _,
+            Response {
+                success: false,
+                stderr,
+                ..
+            },
+        ) => stderr,
+        (Action::Build, Response { stderr, .. }) => stderr,
+        (Action::Clippy, Response { stderr, .. }) => stderr
+            .split('\n')
+            .skip(1)
+            .take_while(|line| !line.contains("Finished dev"))
+            .map(|line| format!("{}\n", line))
+            .collect::<Vec<String>>()
+            .concat(),
+        (
+            Action::Format,
+            Response {
+                code: Some(code), ..
+            },
+        ) => code,
+        (_, Response { stdout, .. }) => stdout,
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChannelResult {
+    pub channel: Channel,
+    pub success: bool,
+    pub output: String,
+    pub diagnostics: Vec<diagnostics::Diagnostic>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChannelMatrix {
+    pub summary: String,
+    pub results: Vec<ChannelResult>,
+}
+
+/// Diagnostics of the response to a sample, pointing at the lines of the sample rather than at
+/// the lines of the code it was wrapped in, or at the lines of its other files when they were
+/// inlined by the backend
+pub fn sample_diagnostics(project: &Project, stderr: &str) -> Vec<diagnostics::Diagnostic> {
+    let inlined = if BACKEND.read().unwrap().inlines_modules() {
+        Some(project.inlined_lines())
+    } else {
+        None
+    };
+    diagnostics::parse(stderr)
+        .into_iter()
+        .map(|mut diagnostic| {
+            if let Some(ref mut span) = diagnostic.span {
+                if span.file.ends_with("main.rs") || span.file.ends_with("lib.rs") {
+                    let origin = match inlined {
+                        // lines added around a module are attributed to the line before them
+                        Some(ref inlined) => inlined
+                            .iter()
+                            .take(span.line as usize)
+                            .rev()
+                            .filter_map(|&(_, ref origin)| origin.clone())
+                            .next(),
+                        None => Some(Origin {
+                            file: None,
+                            line: span.line,
+                        }),
+                    };
+                    match origin {
+                        Some(Origin {
+                            file: Some(file),
+                            line,
+                        }) => {
+                            span.file = format!("src/{}", file);
+                            span.line = line;
+                        }
+                        Some(Origin { file: None, line }) => {
+                            span.line = project.lines.original(line);
+                        }
+                        None => span.line = 1,
+                    }
+                }
+            }
+            diagnostic
+        }).collect()
+}
+
+pub fn ask_channel_matrix(
+    code: &markdown::Code,
+    action: Action,
+    options: Options,
+) -> impl Future<Item = ChannelMatrix, Error = failure::Error> {
+    let attributes = code.attributes;
+    let toolchain = BACKEND.read().unwrap().toolchain();
+    // every channel would run on the same toolchain, so it is run once
+    let channels = match toolchain {
+        Some(_) => vec![options.channel],
+        None => vec![Channel::Stable, Channel::Beta, Channel::Nightly],
+    };
+    future::join_all(
+        channels
+            .iter()
+            .map(|channel| {
+                let channel = *channel;
+                let project = Project::from_code(code);
+                ask_sample(code, action, Options { channel, ..options }).map(move |playground| {
+                    ChannelResult {
+                        channel,
+                        success: attributes.is_success(action, &playground),
+                        diagnostics: sample_diagnostics(&project, &playground.stderr),
+                        output: simplify(action, playground),
+                    }
+                })
+            }).collect::<Vec<_>>(),
+    ).map(move |results| ChannelMatrix {
+        summary: match toolchain {
+            Some(toolchain) => format!(
+                "{} on {}, used for every channel",
+                if results.iter().all(|result| result.success) {
+                    "passes"
+                } else {
+                    "fails"
+                },
+                toolchain
+            ),
+            None => summarize(&results),
+        },
+        results,
+    })
+}
+
+fn summarize(results: &[ChannelResult]) -> String {
+    let channels = |success: bool| {
+        let names = results
+            .iter()
+            .filter(|result| result.success == success)
+            .map(|result| result.channel.name())
+            .collect::<Vec<_>>();
+        match names.split_last() {
+            Some((last, others)) if !others.is_empty() => {
+                format!("{} and {}", others.join(", "), last)
+            }
+            Some((last, _)) => last.to_string(),
+            None => String::new(),
+        }
+    };
+    let failing = channels(false);
+    let passing = channels(true);
+    if failing.is_empty() {
+        format!("passes on {}", passing)
+    } else if passing.is_empty() {
+        format!("fails on {}", failing)
+    } else {
+        format!("fails on {}, passes on {}", failing, passing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn matrix(stable: bool, beta: bool, nightly: bool) -> Vec<ChannelResult> {
+        vec![
+            (Channel::Stable, stable),
+            (Channel::Beta, beta),
+            (Channel::Nightly, nightly),
+        ].into_iter()
+        .map(|(channel, success)| ChannelResult {
+            channel,
+            success,
+            output: String::new(),
+            diagnostics: vec![],
+        }).collect()
+    }
+
+    #[test]
+    fn channel_matrix_summary() {
+        assert_eq!(
+            summarize(&matrix(true, true, true)),
+            "passes on stable, beta and nightly"
+        );
+        assert_eq!(
+            summarize(&matrix(true, true, false)),
+            "fails on nightly, passes on stable and beta"
+        );
+        assert_eq!(
+            summarize(&matrix(false, true, true)),
+            "fails on stable, passes on beta and nightly"
+        );
+        assert_eq!(
+            summarize(&matrix(false, false, false)),
+            "fails on stable, beta and nightly"
+        );
+    }
+
+    #[test]
+    fn query_has_the_fields_of_the_playground() {
+        let query = Query::from(
+            Action::Test,
+            Project::from_source("fn main() {}"),
+            Options::default(),
+        );
+        let query = serde_json::to_value(&query).unwrap();
+        assert_eq!(query["tests"], json!(true));
+        assert_eq!(query["crateType"], json!("bin"));
+        assert_eq!(query["channel"], json!("stable"));
+        assert_eq!(query["mode"], json!("debug"));
+        assert!(query.get("test").is_none());
+        assert!(query.get("target").is_none());
+    }
+
+    fn project(code: &str, files: &[(&str, &str)]) -> Project {
+        Project {
+            files: files
+                .iter()
+                .map(|&(name, content)| SourceFile {
+                    name: name.to_string(),
+                    content: content.to_string(),
+                }).collect(),
+            ..Project::from_source(code)
+        }
+    }
+
+    #[test]
+    fn modules_are_inlined_recursively() {
+        let project = project(
+            "mod a;\n\nfn main() {\n    a::b::c();\n}\n",
+            &[
+                ("a.rs", "pub mod b;\n"),
+                ("b.rs", "pub fn c() {}\nmod a;\n"),
+                ("notes.txt", "mod a;\n"),
+            ],
+        );
+        assert_eq!(
+            project.inline_modules(),
+            "mod a {\npub mod b {\npub fn c() {}\nmod a;\n}\n}\n\nfn main() {\n    a::b::c();\n}\n"
+        );
+    }
+
+    #[test]
+    fn diagnostics_point_at_inlined_files() {
+        let project = project(
+            "extern crate playground;\nmod a;\n\nfn main() {\n    a::f();\n}\n",
+            &[
+                ("a.rs", "pub fn f() {\n    let x: u32 = \"\";\n}\n"),
+                ("lib.rs", "pub fn g() {\n    1 + \"\";\n}\n"),
+            ],
+        );
+        assert_eq!(
+            project.inline_modules(),
+            "mod a {\npub fn f() {\n    let x: u32 = \"\";\n}\n}\n\nfn main() {\n    a::f();\n}\n\n\
+             pub mod playground {\npub fn g() {\n    1 + \"\";\n}\n}\n"
+        );
+        let stderr = |line: u32| {
+            format!(
+                "error[E0308]: mismatched types\n --> src/main.rs:{}:5\n  |\n\n",
+                line
+            )
+        };
+        for &(line, file, original) in &[
+            (3, "src/a.rs", 2),
+            (8, "src/main.rs", 5),
+            (13, "src/lib.rs", 2),
+        ] {
+            let span = sample_diagnostics(&project, &stderr(line))[0]
+                .span
+                .clone()
+                .unwrap();
+            assert_eq!((span.file.as_str(), span.line), (file, original));
+        }
+    }
+
+    #[test]
+    fn lib_is_kept_next_to_main() {
+        let project = project(
+            "extern crate playground;\n\nfn main() {\n    playground::f();\n}\n",
+            &[
+                ("lib.rs", "mod g;\npub fn f() {}\n"),
+                ("g.rs", "fn g() {}\n"),
+            ],
+        );
+        assert_eq!(
+            project.inline_modules(),
+            "\nfn main() {\n    playground::f();\n}\n\npub mod playground {\nmod g {\nfn g() {}\n}\npub fn f() {}\n}\n"
+        );
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::client::ClientResponse;
+use actix_web::HttpMessage;
+use failure;
+use futures::future::{self, Future, Loop};
+use futures::sync::oneshot;
+use http::{header::RETRY_AFTER, HeaderMap, StatusCode};
+use tokio_timer::Delay;
+
+/// Seconds after which the wait between retries stops doubling
+const MAX_BACKOFF: u64 = 60;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Projects built or run at the same time, by any backend
+    pub max_in_flight: usize,
+    /// Time between the start of two requests to the same host
+    pub min_interval: Duration,
+    /// Attempts after the first one for requests failing with `429 Too Many Requests` or a
+    /// server error
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each following one
+    pub backoff: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_in_flight: 8,
+            min_interval: Duration::from_millis(250),
+            max_retries: 3,
+            backoff: Duration::new(1, 0),
+        }
+    }
+}
+
+/// Why an attempt failed
+pub enum Failure {
+    /// The host is overloaded, try again later, not before the given time if it said so
+    Retry(failure::Error, Option<Duration>),
+    Fatal(failure::Error),
+}
+
+/// Keep answers with a status meaning the request can be tried again out of the way
+pub fn check_status(url: &str, resp: ClientResponse) -> Result<ClientResponse, Failure> {
+    let status = resp.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        Err(Failure::Retry(
+            failure::err_msg(format!("{} answered with {}", url, status)),
+            retry_after(resp.headers()),
+        ))
+    } else {
+        Ok(resp)
+    }
+}
+
+/// Wait asked by the host, only the number of seconds form of `Retry-After` being supported
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(|seconds| Duration::new(seconds, 0))
+}
+
+struct State {
+    limits: Limits,
+    in_flight: usize,
+    /// Queues with waiting projects, each one starting a project in turn
+    queues: VecDeque<(usize, VecDeque<oneshot::Sender<Permit>>)>,
+    /// Earliest start of the next request to each host
+    next_start: HashMap<String, Instant>,
+}
+
+impl State {
+    fn new(limits: Limits) -> Arc<Mutex<State>> {
+        Arc::new(Mutex::new(State {
+            limits,
+            in_flight: 0,
+            queues: VecDeque::new(),
+            next_start: HashMap::new(),
+        }))
+    }
+}
+
+lazy_static! {
+    static ref STATE: Arc<Mutex<State>> = { State::new(Limits::default()) };
+}
+
+static QUEUE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Queue of the task, a connection to the web server or a run of the command line, so that one
+// request checking many samples does not delay the others until it is done
+task_local! {
+    static QUEUE: usize = QUEUE_COUNTER.fetch_add(1, Ordering::SeqCst)
+}
+
+pub fn set_limits(limits: Limits) {
+    let mut state = STATE.lock().unwrap();
+    state.limits = limits;
+    dispatch(&STATE, &mut state);
+}
+
+/// A place among the projects in flight, given back when dropped
+struct Permit {
+    scheduler: Arc<Mutex<State>>,
+    taken: bool,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.taken {
+            let mut state = self.scheduler.lock().unwrap();
+            state.in_flight -= 1;
+            dispatch(&self.scheduler, &mut state);
+        }
+    }
+}
+
+/// Give the free places to the waiting projects, taking one from each queue in turn
+fn dispatch(scheduler: &Arc<Mutex<State>>, state: &mut State) {
+    while state.in_flight < state.limits.max_in_flight {
+        let (queue, mut waiting) = match state.queues.pop_front() {
+            Some(queue) => queue,
+            None => return,
+        };
+        let next = waiting.pop_front();
+        if !waiting.is_empty() {
+            state.queues.push_back((queue, waiting));
+        }
+        let next = match next {
+            Some(next) => next,
+            None => continue,
+        };
+        let permit = Permit {
+            scheduler: scheduler.clone(),
+            taken: true,
+        };
+        match next.send(permit) {
+            Ok(()) => state.in_flight += 1,
+            // the project was dropped while waiting, its place goes to the next one
+            Err(mut permit) => permit.taken = false,
+        }
+    }
+}
+
+fn acquire(scheduler: &Arc<Mutex<State>>, queue: usize) -> oneshot::Receiver<Permit> {
+    let (sender, receiver) = oneshot::channel();
+    let mut state = scheduler.lock().unwrap();
+    match state.queues.iter().position(|&(id, _)| id == queue) {
+        Some(position) => state.queues[position].1.push_back(sender),
+        None => {
+            let mut new_queue = VecDeque::new();
+            new_queue.push_back(sender);
+            state.queues.push_back((queue, new_queue));
+        }
+    }
+    dispatch(scheduler, &mut state);
+    receiver
+}
+
+/// When the next request to `host` can start, the one after waiting `min_interval` more
+fn reserve_start(scheduler: &Arc<Mutex<State>>, host: &str) -> Instant {
+    let mut state = scheduler.lock().unwrap();
+    let now = Instant::now();
+    let start = match state.next_start.get(host) {
+        Some(next_start) if *next_start > now => *next_start,
+        _ => now,
+    };
+    let min_interval = state.limits.min_interval;
+    state
+        .next_start
+        .insert(host.to_string(), start + min_interval);
+    start
+}
+
+/// No request to `host` starts before `until`
+fn hold(scheduler: &Arc<Mutex<State>>, host: &str, until: Instant) {
+    let mut state = scheduler.lock().unwrap();
+    let next_start = state.next_start.entry(host.to_string()).or_insert(until);
+    if *next_start < until {
+        *next_start = until;
+    }
+}
+
+fn wait_until(until: Instant) -> Box<Future<Item = (), Error = failure::Error>> {
+    if until > Instant::now() {
+        Box::new(Delay::new(until).map_err(|err| err.into()))
+    } else {
+        Box::new(future::ok(()))
+    }
+}
+
+/// Run the future made by `run` once there is a free place among the projects in flight, the
+/// tasks waiting for one taking turns
+pub fn limit<T, F, R>(run: F) -> Box<Future<Item = T, Error = failure::Error>>
+where
+    F: FnOnce() -> R + 'static,
+    R: Future<Item = T, Error = failure::Error> + 'static,
+    T: 'static,
+{
+    limit_on(STATE.clone(), run)
+}
+
+fn limit_on<T, F, R>(
+    scheduler: Arc<Mutex<State>>,
+    run: F,
+) -> Box<Future<Item = T, Error = failure::Error>>
+where
+    F: FnOnce() -> R + 'static,
+    R: Future<Item = T, Error = failure::Error> + 'static,
+    T: 'static,
+{
+    // the queue of the task is only known once polled
+    Box::new(
+        future::lazy(|| Ok(QUEUE.with(|queue| *queue)))
+            .and_then(move |queue| {
+                acquire(&scheduler, queue)
+                    .map_err(|_| failure::err_msg("project dropped by the scheduler"))
+            }).and_then(move |permit| {
+                run().then(move |result| {
+                    drop(permit);
+                    result
+                })
+            }),
+    )
+}
+
+/// Run `attempt` once the rate of requests to `host` allows it, and again with exponential
+/// backoff while it fails with `Failure::Retry`
+pub fn schedule<T, F>(host: &str, attempt: F) -> Box<Future<Item = T, Error = failure::Error>>
+where
+    F: Fn() -> Box<Future<Item = T, Error = Failure>> + 'static,
+    T: 'static,
+{
+    schedule_on(STATE.clone(), host, attempt)
+}
+
+fn schedule_on<T, F>(
+    scheduler: Arc<Mutex<State>>,
+    host: &str,
+    attempt: F,
+) -> Box<Future<Item = T, Error = failure::Error>>
+where
+    F: Fn() -> Box<Future<Item = T, Error = Failure>> + 'static,
+    T: 'static,
+{
+    let host = host.to_string();
+    let attempt = Rc::new(attempt);
+    Box::new(future::loop_fn(0, move |retries: u32| {
+        let scheduler = scheduler.clone();
+        let attempt = attempt.clone();
+        let host = host.clone();
+        let limits = scheduler.lock().unwrap().limits;
+        wait_until(reserve_start(&scheduler, &host)).and_then(move |()| {
+            attempt().then(move |result| retry_or_break(&scheduler, &host, result, retries, limits))
+        })
+    }))
+}
+
+fn retry_or_break<T>(
+    scheduler: &Arc<Mutex<State>>,
+    host: &str,
+    result: Result<T, Failure>,
+    retries: u32,
+    limits: Limits,
+) -> Box<Future<Item = Loop<T, u32>, Error = failure::Error>>
+where
+    T: 'static,
+{
+    match result {
+        Ok(item) => Box::new(future::ok(Loop::Break(item))),
+        Err(Failure::Fatal(err)) => Box::new(future::err(err)),
+        Err(Failure::Retry(err, retry_after)) => {
+            if retries >= limits.max_retries {
+                return Box::new(future::err(err));
+            }
+            let wait = retry_after.unwrap_or_else(|| backoff(retries, limits));
+            warn!("{}, trying again in {} seconds", err, wait.as_secs());
+            let until = Instant::now() + wait;
+            // other requests to an overloaded host wait too
+            hold(scheduler, host, until);
+            Box::new(wait_until(until).map(move |()| Loop::Continue(retries + 1)))
+        }
+    }
+}
+
+/// Wait before retrying after `retries` retries, when the host did not say how long
+fn backoff(retries: u32, limits: Limits) -> Duration {
+    let max = Duration::new(MAX_BACKOFF, 0);
+    if retries >= 16 {
+        return max;
+    }
+    (limits.backoff * 2u32.pow(retries)).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Async;
+    use http::HeaderValue;
+    use std::cell::Cell;
+
+    fn scheduler(max_in_flight: usize) -> Arc<Mutex<State>> {
+        State::new(Limits {
+            max_in_flight,
+            min_interval: Duration::new(0, 0),
+            max_retries: 2,
+            backoff: Duration::new(1, 0),
+        })
+    }
+
+    /// Permits given to the waiting projects, polling them in a task as the scheduler would
+    fn ready(waiting: &mut [Option<oneshot::Receiver<Permit>>]) -> Vec<Option<Permit>> {
+        future::lazy(|| -> Result<_, ()> {
+            Ok(waiting
+                .iter_mut()
+                .map(|receiver| {
+                    let permit = match receiver.as_mut().map(|receiver| receiver.poll()) {
+                        Some(Ok(Async::Ready(permit))) => Some(permit),
+                        Some(Ok(Async::NotReady)) | None => None,
+                        Some(Err(_)) => panic!("permit dropped"),
+                    };
+                    if permit.is_some() {
+                        *receiver = None;
+                    }
+                    permit
+                }).collect())
+        }).wait()
+        .unwrap()
+    }
+
+    #[test]
+    fn permit_given_back_when_dropped() {
+        let scheduler = scheduler(1);
+        let mut waiting = vec![Some(acquire(&scheduler, 1)), Some(acquire(&scheduler, 1))];
+        let permits = ready(&mut waiting);
+        assert!(permits[0].is_some());
+        assert!(permits[1].is_none());
+        assert_eq!(scheduler.lock().unwrap().in_flight, 1);
+
+        drop(permits);
+        assert_eq!(scheduler.lock().unwrap().in_flight, 1);
+        let permits = ready(&mut waiting);
+        assert!(permits[1].is_some());
+        drop(permits);
+        assert_eq!(scheduler.lock().unwrap().in_flight, 0);
+    }
+
+    #[test]
+    fn queues_take_turns() {
+        let scheduler = scheduler(1);
+        let mut running = vec![Some(acquire(&scheduler, 0))];
+        let running = ready(&mut running);
+        let mut waiting = vec![
+            Some(acquire(&scheduler, 1)),
+            Some(acquire(&scheduler, 1)),
+            Some(acquire(&scheduler, 1)),
+            Some(acquire(&scheduler, 2)),
+        ];
+        assert!(ready(&mut waiting).iter().all(|permit| permit.is_none()));
+        drop(running);
+
+        let mut order = vec![];
+        for _ in 0..4 {
+            let permits = ready(&mut waiting);
+            assert_eq!(permits.iter().filter(|permit| permit.is_some()).count(), 1);
+            order.push(permits.iter().position(|permit| permit.is_some()).unwrap());
+        }
+        assert_eq!(order, vec![0, 3, 1, 2]);
+    }
+
+    #[test]
+    fn dropped_waiting_project_gives_its_place() {
+        let scheduler = scheduler(1);
+        let running = ready(&mut [Some(acquire(&scheduler, 0))]);
+        let dropped = acquire(&scheduler, 1);
+        let mut waiting = [Some(acquire(&scheduler, 2))];
+        drop(dropped);
+
+        drop(running);
+        let permits = ready(&mut waiting);
+        assert!(permits[0].is_some());
+        assert_eq!(scheduler.lock().unwrap().in_flight, 1);
+        assert!(scheduler.lock().unwrap().queues.is_empty());
+    }
+
+    #[test]
+    fn limit_runs_once_a_place_is_free() {
+        let scheduler = scheduler(1);
+        let result = limit_on(scheduler.clone(), || future::ok(42)).wait();
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(scheduler.lock().unwrap().in_flight, 0);
+    }
+
+    #[test]
+    fn requests_to_a_host_are_spaced() {
+        let scheduler = scheduler(1);
+        scheduler.lock().unwrap().limits.min_interval = Duration::from_millis(100);
+        let first = reserve_start(&scheduler, "play.rust-lang.org");
+        let second = reserve_start(&scheduler, "play.rust-lang.org");
+        let other = reserve_start(&scheduler, "example.com");
+        assert!(second >= first + Duration::from_millis(100));
+        assert!(other < first + Duration::from_millis(100));
+
+        let until = Instant::now() + Duration::new(10, 0);
+        hold(&scheduler, "play.rust-lang.org", until);
+        assert!(reserve_start(&scheduler, "play.rust-lang.org") >= until);
+        hold(&scheduler, "play.rust-lang.org", Instant::now());
+        assert!(reserve_start(&scheduler, "play.rust-lang.org") > until);
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::new(120, 0)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_cap() {
+        let limits = Limits::default();
+        assert_eq!(backoff(0, limits), Duration::new(1, 0));
+        assert_eq!(backoff(3, limits), Duration::new(8, 0));
+        assert_eq!(backoff(6, limits), Duration::new(MAX_BACKOFF, 0));
+        assert_eq!(backoff(40, limits), Duration::new(MAX_BACKOFF, 0));
+    }
+
+    #[test]
+    fn retry_continues_until_max_retries() {
+        let scheduler = scheduler(1);
+        let limits = scheduler.lock().unwrap().limits;
+        let retry = || {
+            Err(Failure::Retry(
+                failure::err_msg("busy"),
+                Some(Duration::new(0, 0)),
+            ))
+        };
+
+        match retry_or_break::<()>(&scheduler, "host", retry(), 0, limits).wait() {
+            Ok(Loop::Continue(1)) => (),
+            _ => panic!("should retry"),
+        }
+        assert!(scheduler.lock().unwrap().next_start.contains_key("host"));
+        assert!(retry_or_break::<()>(&scheduler, "host", retry(), 2, limits)
+            .wait()
+            .is_err());
+        let fatal = Err(Failure::Fatal(failure::err_msg("bad request")));
+        assert!(retry_or_break::<()>(&scheduler, "host", fatal, 0, limits)
+            .wait()
+            .is_err());
+        match retry_or_break(&scheduler, "host", Ok(42), 0, limits).wait() {
+            Ok(Loop::Break(42)) => (),
+            _ => panic!("should stop"),
+        }
+    }
+
+    #[test]
+    fn schedule_retries_failed_attempts() {
+        let attempts = Rc::new(Cell::new(0));
+        let counted = attempts.clone();
+        let result = schedule_on(scheduler(1), "host", move || {
+            counted.set(counted.get() + 1);
+            if counted.get() < 3 {
+                Box::new(future::err(Failure::Retry(
+                    failure::err_msg("busy"),
+                    Some(Duration::new(0, 0)),
+                )))
+            } else {
+                Box::new(future::ok(42))
+            }
+        }).wait();
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.get(), 3);
+
+        let attempts = Rc::new(Cell::new(0));
+        let counted = attempts.clone();
+        let result = schedule_on(scheduler(1), "host", move || {
+            counted.set(counted.get() + 1);
+            Box::new(future::err::<(), _>(Failure::Retry(
+                failure::err_msg("busy"),
+                Some(Duration::new(0, 0)),
+            )))
+        }).wait();
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+    }
+}
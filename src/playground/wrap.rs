@@ -0,0 +1,578 @@
+use super::CrateType;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Ending {
+    /// Items like `fn`, `impl` or `struct`, ending with their body or with a `;`
+    Body,
+    /// Items like `use`, `const` or `static`, always ending with a `;`
+    Semicolon,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Kind {
+    InnerAttribute,
+    Item(Ending),
+    Statement,
+    /// Only whitespace and comments
+    Trivia,
+}
+
+#[derive(Debug)]
+struct Segment<'a> {
+    text: &'a str,
+    /// Offset of the text in the code
+    start: usize,
+    kind: Kind,
+}
+
+/// Where the lines of wrapped code come from in the sample
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LineMap {
+    /// Line of the sample for each line of the wrapped code, none for lines added by the
+    /// wrapping. No map when the code was not changed.
+    lines: Option<Vec<Option<u32>>>,
+}
+impl LineMap {
+    /// Line of the sample for a line of the wrapped code, both starting at 1. Added lines are
+    /// attributed to the closest line of the sample before them.
+    pub fn original(&self, line: u32) -> u32 {
+        match self.lines {
+            Some(ref lines) => lines
+                .iter()
+                .take(line as usize)
+                .rev()
+                .filter_map(|original| *original)
+                .next()
+                .unwrap_or(1),
+            None => line,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Wrapped {
+    pub code: String,
+    pub crate_type: CrateType,
+    pub lines: LineMap,
+}
+
+/// Make a code sample compilable: items and inner attributes are kept at the top level and
+/// statements are moved in a `fn main`. Samples with only items and no `main` are built as a
+/// library, or as a procedural macro crate if they define one. A `#![crate_type]` attribute
+/// takes precedence.
+pub fn wrap(code: &str) -> Wrapped {
+    let unchanged = |crate_type| Wrapped {
+        code: code.to_string(),
+        crate_type,
+        lines: LineMap::default(),
+    };
+    let segments = split(code);
+    if let Some(crate_type) = declared_crate_type(&segments) {
+        return unchanged(crate_type);
+    }
+    let has_main = segments.iter().any(|segment| match segment.kind {
+        Kind::Item(_) => is_main(segment.text),
+        _ => false,
+    });
+    if has_main {
+        return unchanged(CrateType::Bin);
+    }
+    if !segments
+        .iter()
+        .any(|segment| segment.kind == Kind::Statement)
+    {
+        let is_proc_macro = segments.iter().any(|segment| match segment.kind {
+            Kind::Item(_) => segment.text.contains("#[proc_macro"),
+            _ => false,
+        });
+        return unchanged(if is_proc_macro {
+            CrateType::ProcMacro
+        } else {
+            CrateType::Lib
+        });
+    }
+    let join = |statements: bool| {
+        let mut lines = vec![];
+        let texts = segments
+            .iter()
+            .filter(|segment| (segment.kind == Kind::Statement) == statements)
+            .map(|segment| {
+                let is_newline = |c: char| c == '\n' || c == '\r';
+                let text = segment.text.trim_matches(is_newline);
+                let start = segment.start + segment.text.len()
+                    - segment.text.trim_left_matches(is_newline).len();
+                (text, line_at(code, start))
+            }).filter(|&(text, _)| !text.trim().is_empty())
+            .map(|(text, first_line)| {
+                lines.extend((0..text.split('\n').count() as u32).map(|i| Some(first_line + i)));
+                text
+            }).collect::<Vec<_>>()
+            .join("\n");
+        (texts, lines)
+    };
+    let (items, mut lines) = join(false);
+    let (statements, statement_lines) = join(true);
+    if lines.is_empty() {
+        lines.push(None);
+    }
+    // a blank line and `fn main() {` before the statements, and `}` after them
+    lines.extend(vec![None, None]);
+    lines.extend(statement_lines);
+    lines.push(None);
+    Wrapped {
+        code: format!("{}\n\nfn main() {{\n{}\n}}\n", items, statements),
+        crate_type: CrateType::Bin,
+        lines: LineMap { lines: Some(lines) },
+    }
+}
+
+/// Line of the byte at `offset`, starting at 1
+fn line_at(code: &str, offset: usize) -> u32 {
+    code[..offset].bytes().filter(|c| *c == b'\n').count() as u32 + 1
+}
+
+/// Crate type set with a `#![crate_type = "..."]` inner attribute
+fn declared_crate_type(segments: &[Segment]) -> Option<CrateType> {
+    segments
+        .iter()
+        .filter(|segment| segment.kind == Kind::InnerAttribute)
+        .filter_map(|segment| {
+            let attribute = segment.text.trim();
+            let value = attribute[attribute.find("crate_type")? + "crate_type".len()..]
+                .trim_left()
+                .trim_left_matches('=')
+                .trim_left();
+            if value.starts_with("\"bin\"") {
+                Some(CrateType::Bin)
+            } else if value.starts_with("\"proc-macro\"") {
+                Some(CrateType::ProcMacro)
+            } else if value.starts_with('"') {
+                Some(CrateType::Lib)
+            } else {
+                None
+            }
+        }).next()
+}
+
+/// Split code at the top level between inner attributes, items and statements
+fn split(code: &str) -> Vec<Segment> {
+    let bytes = code.as_bytes();
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut depth = 0;
+    let mut has_content = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(end) = skip_trivia(bytes, i) {
+            i = end;
+            continue;
+        }
+        if !has_content && depth == 0 && bytes[i..].starts_with(b"#![") {
+            let end = skip_group(bytes, i + 2);
+            segments.push(Segment {
+                text: &code[start..end],
+                start,
+                kind: Kind::InnerAttribute,
+            });
+            start = end;
+            i = end;
+            continue;
+        }
+        has_content = true;
+        if let Some(end) = skip_literal(bytes, i) {
+            i = end;
+            continue;
+        }
+        let mut end_of_segment = false;
+        match bytes[i] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' => depth -= 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    end_of_segment = match classify(&code[start..=i]) {
+                        Kind::Item(Ending::Semicolon) => false,
+                        Kind::Statement => !continues(&code[i + 1..]),
+                        _ => true,
+                    };
+                }
+            }
+            b';' if depth == 0 => end_of_segment = true,
+            _ => (),
+        }
+        i += 1;
+        if end_of_segment {
+            segments.push(Segment {
+                text: &code[start..i],
+                start,
+                kind: classify(&code[start..i]),
+            });
+            start = i;
+            has_content = false;
+        }
+    }
+    if start < code.len() {
+        segments.push(Segment {
+            text: &code[start..],
+            start,
+            kind: classify(&code[start..]),
+        });
+    }
+    segments
+}
+
+fn classify(text: &str) -> Kind {
+    let tokens = tokens(text);
+    if tokens.is_empty() {
+        return Kind::Trivia;
+    }
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1).cloned();
+        match *token {
+            "pub" | "default" => (),
+            "unsafe" if next == Some("{") => return Kind::Statement,
+            "unsafe" => (),
+            "async" if next == Some("fn") || next == Some("unsafe") => (),
+            "const" if next == Some("fn") || next == Some("unsafe") || next == Some("async") => (),
+            "const" | "static" | "use" | "type" => return Kind::Item(Ending::Semicolon),
+            "extern" if next == Some("crate") => return Kind::Item(Ending::Semicolon),
+            "extern" | "fn" | "struct" | "enum" | "union" | "impl" | "trait" | "mod" | "auto"
+            | "macro_rules" => return Kind::Item(Ending::Body),
+            _ => return Kind::Statement,
+        }
+    }
+    Kind::Statement
+}
+
+/// Whether the item is a `main` function, rather than an item with a `main` function inside
+fn is_main(item: &str) -> bool {
+    let tokens = tokens(item);
+    let mut tokens = tokens
+        .iter()
+        .skip_while(|token| ["pub", "unsafe", "async", "const", "extern"].contains(token));
+    tokens.next() == Some(&"fn") && tokens.next() == Some(&"main")
+}
+
+/// Whether an expression ending with a block continues after it, like `if {} else {}`
+fn continues(rest: &str) -> bool {
+    let bytes = rest.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match skip_trivia(bytes, i) {
+            Some(end) => i = end,
+            None => break,
+        }
+    }
+    let rest = &rest[i..];
+    match rest.bytes().next() {
+        Some(c) if b".?;,)]+-*/%&|^<>=!".contains(&c) => true,
+        Some(_) => starts_with_keyword(rest, "else") || starts_with_keyword(rest, "as"),
+        None => false,
+    }
+}
+
+fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    text.starts_with(keyword)
+        && !text[keyword.len()..]
+            .bytes()
+            .next()
+            .map(is_identifier)
+            .unwrap_or(false)
+}
+
+/// First significant tokens of an item or statement, skipping attributes, comments, string
+/// literals and visibility restrictions
+fn tokens(text: &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() && tokens.len() < 8 {
+        if let Some(end) = skip_trivia(bytes, i) {
+            i = end;
+            continue;
+        }
+        if bytes[i] == b'#' {
+            let bracket = if bytes.get(i + 1) == Some(&b'!') {
+                i + 2
+            } else {
+                i + 1
+            };
+            if bytes.get(bracket) == Some(&b'[') {
+                i = skip_group(bytes, bracket);
+                continue;
+            }
+        }
+        if let Some(end) = skip_literal(bytes, i) {
+            i = end;
+            continue;
+        }
+        if is_identifier(bytes[i]) {
+            let start = i;
+            while i < bytes.len() && is_identifier(bytes[i]) {
+                i += 1;
+            }
+            tokens.push(&text[start..i]);
+            if &text[start..i] == "pub" {
+                let mut next = i;
+                while next < bytes.len() && bytes[next].is_ascii_whitespace() {
+                    next += 1;
+                }
+                if bytes.get(next) == Some(&b'(') {
+                    i = skip_group(bytes, next);
+                }
+            }
+        } else {
+            tokens.push(&text[i..=i]);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Non ASCII bytes are considered part of identifiers so that tokens stay on char boundaries
+fn is_identifier(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || !c.is_ascii()
+}
+
+/// End of the whitespace or comment starting at `i`
+fn skip_trivia(bytes: &[u8], i: usize) -> Option<usize> {
+    if bytes[i].is_ascii_whitespace() {
+        return Some(i + 1);
+    }
+    match (bytes[i], bytes.get(i + 1)) {
+        (b'/', Some(b'/')) => Some(
+            bytes[i..]
+                .iter()
+                .position(|c| *c == b'\n')
+                .map(|position| i + position + 1)
+                .unwrap_or_else(|| bytes.len()),
+        ),
+        (b'/', Some(b'*')) => {
+            let mut depth = 0;
+            let mut j = i;
+            while j < bytes.len() {
+                if bytes[j..].starts_with(b"/*") {
+                    depth += 1;
+                    j += 2;
+                } else if bytes[j..].starts_with(b"*/") {
+                    depth -= 1;
+                    j += 2;
+                    if depth == 0 {
+                        return Some(j);
+                    }
+                } else {
+                    j += 1;
+                }
+            }
+            Some(bytes.len())
+        }
+        _ => None,
+    }
+}
+
+/// End of the string, byte string, raw string or character literal starting at `i`
+fn skip_literal(bytes: &[u8], i: usize) -> Option<usize> {
+    if i > 0 && is_identifier(bytes[i - 1]) {
+        return None;
+    }
+    let mut j = i;
+    if bytes[j] == b'b' {
+        j += 1;
+    }
+    if bytes.get(j) == Some(&b'r') {
+        let mut hashes = 0;
+        let mut k = j + 1;
+        while bytes.get(k) == Some(&b'#') {
+            hashes += 1;
+            k += 1;
+        }
+        if bytes.get(k) != Some(&b'"') {
+            return None;
+        }
+        let mut closing = vec![b'"'];
+        closing.extend(::std::iter::repeat(b'#').take(hashes));
+        return Some(
+            (k + 1..bytes.len())
+                .find(|end| bytes[*end..].starts_with(&closing))
+                .map(|end| end + closing.len())
+                .unwrap_or_else(|| bytes.len()),
+        );
+    }
+    match bytes.get(j) {
+        Some(b'"') => {
+            let mut k = j + 1;
+            while k < bytes.len() {
+                match bytes[k] {
+                    b'\\' => k += 2,
+                    b'"' => return Some(k + 1),
+                    _ => k += 1,
+                }
+            }
+            Some(bytes.len())
+        }
+        Some(b'\'') => {
+            // a lifetime is not closed by a quote right after its first character
+            match (bytes.get(j + 1), bytes.get(j + 2)) {
+                (Some(b'\\'), _) => bytes[j + 2..]
+                    .iter()
+                    .skip(1)
+                    .position(|c| *c == b'\'')
+                    .map(|position| j + 4 + position),
+                (Some(_), Some(b'\'')) => Some(j + 3),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// End of the bracketed group opening at `i`, literals and comments included
+fn skip_group(bytes: &[u8], i: usize) -> usize {
+    let mut depth = 0;
+    let mut j = i;
+    while j < bytes.len() {
+        if let Some(end) = skip_trivia(bytes, j).or_else(|| skip_literal(bytes, j)) {
+            j = end;
+            continue;
+        }
+        match bytes[j] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return j + 1;
+                }
+            }
+            _ => (),
+        }
+        j += 1;
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of the wrapped code with the line of the sample they come from, without the
+    /// lines added around the statements
+    fn mapped_lines(wrapped: &Wrapped) -> Vec<(&str, u32)> {
+        let last = wrapped.code.lines().count() - 1;
+        wrapped
+            .code
+            .lines()
+            .enumerate()
+            .filter(|&(i, line)| !line.trim().is_empty() && line != "fn main() {" && i != last)
+            .map(|(i, line)| (line, wrapped.lines.original(i as u32 + 1)))
+            .collect()
+    }
+
+    #[test]
+    fn main_at_the_top_level_is_kept() {
+        for code in &[
+            "fn main() {\n    println!(\"Hello\");\n}\n",
+            "#[allow(unused)]\npub fn main() {}\n",
+            "use std::fmt;\n\nasync unsafe fn main() {}\n",
+            "extern \"C\" fn main() {}\n",
+        ] {
+            let wrapped = wrap(code);
+            assert_eq!(wrapped.code, *code);
+            assert_eq!(wrapped.crate_type, CrateType::Bin);
+            assert_eq!(wrapped.lines.original(2), 2);
+        }
+    }
+
+    #[test]
+    fn main_inside_an_item_is_not_the_main_function() {
+        let wrapped = wrap("struct X;\nimpl X {\n    fn main() {}\n}\nX::main();\n");
+        assert_eq!(
+            wrapped.code,
+            "struct X;\nimpl X {\n    fn main() {}\n}\n\nfn main() {\nX::main();\n}\n"
+        );
+        assert_eq!(wrapped.crate_type, CrateType::Bin);
+
+        let wrapped = wrap("trait T {\n    fn main();\n}\nmod m {\n    fn main() {}\n}\n");
+        assert_eq!(wrapped.crate_type, CrateType::Lib);
+    }
+
+    #[test]
+    fn items_and_attributes_are_hoisted() {
+        let wrapped = wrap(
+            "#![allow(unused)]\nlet a = 1;\nuse std::fmt;\n#[derive(Debug)]\nstruct A;\nprintln!(\"{:?}\", A);\n",
+        );
+        assert_eq!(
+            wrapped.code,
+            "#![allow(unused)]\nuse std::fmt;\n#[derive(Debug)]\nstruct A;\n\nfn main() {\nlet a = 1;\nprintln!(\"{:?}\", A);\n}\n"
+        );
+        assert_eq!(wrapped.crate_type, CrateType::Bin);
+    }
+
+    #[test]
+    fn statements_with_blocks_stay_whole() {
+        let wrapped = wrap(
+            "let x = if true { 1 } else { 2 };\nif x > 1 {\n    println!(\"big\");\n}\nfn f() {}\n",
+        );
+        assert_eq!(
+            wrapped.code,
+            "fn f() {}\n\nfn main() {\nlet x = if true { 1 } else { 2 };\nif x > 1 {\n    println!(\"big\");\n}\n}\n"
+        );
+    }
+
+    #[test]
+    fn literals_and_comments_do_not_split() {
+        let code =
+            "let s = \"fn main() { ; }\";\n// fn main() {}\nlet r = r#\"}\"#;\nlet c = '}';\n";
+        let wrapped = wrap(code);
+        assert_eq!(wrapped.crate_type, CrateType::Bin);
+        assert!(wrapped
+            .code
+            .starts_with("\n\nfn main() {\nlet s = \"fn main() { ; }\";\n"));
+    }
+
+    #[test]
+    fn crate_type_from_items() {
+        assert_eq!(
+            wrap("pub trait T {}\nimpl T for u8 {}\n").crate_type,
+            CrateType::Lib
+        );
+        assert_eq!(
+            wrap("#[proc_macro]\npub fn m(input: TokenStream) -> TokenStream { input }\n")
+                .crate_type,
+            CrateType::ProcMacro
+        );
+        let wrapped = wrap("#![crate_type = \"lib\"]\nlet x = 1;\n");
+        assert_eq!(wrapped.crate_type, CrateType::Lib);
+        assert_eq!(wrapped.code, "#![crate_type = \"lib\"]\nlet x = 1;\n");
+    }
+
+    #[test]
+    fn lines_map_back_to_the_sample() {
+        let sample = "let a = 1;\n\nuse std::fmt;\n\n#[derive(Debug)]\nstruct A {\n    x: u8,\n}\nlet b = a + 1;\n// done\nprintln!(\"{}\", b);\n";
+        let wrapped = wrap(sample);
+        let sample_lines = sample.lines().collect::<Vec<_>>();
+        let mapped = mapped_lines(&wrapped);
+        assert_eq!(mapped.len(), 9);
+        for (line, original) in mapped {
+            assert_eq!(line, sample_lines[original as usize - 1]);
+        }
+        // `fn main() {` and `}` are attributed to the sample line before them
+        let main = wrapped
+            .code
+            .lines()
+            .position(|line| line == "fn main() {")
+            .unwrap() as u32
+            + 1;
+        assert_eq!(wrapped.lines.original(main), 8);
+        let last = wrapped.code.lines().count() as u32;
+        assert_eq!(wrapped.lines.original(last), 11);
+    }
+
+    #[test]
+    fn lines_of_statements_only() {
+        let wrapped = wrap("\n\nlet a = 1;\nlet b = 2;\n");
+        assert_eq!(wrapped.code, "\n\nfn main() {\nlet a = 1;\nlet b = 2;\n}\n");
+        assert_eq!(wrapped.lines.original(4), 3);
+        assert_eq!(wrapped.lines.original(5), 4);
+        assert_eq!(wrapped.lines.original(1), 1);
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use failure;
+
+use github::cache;
+use playground::{self, local, scheduler};
+use store;
+
+/// Settings shared by the command line and the web server, flattened in their arguments
+#[derive(StructOpt, Debug)]
+pub struct Settings {
+    /// Origin of the playground used to run code samples and to recognise shared links
+    #[structopt(
+        long = "playground-url",
+        env = "PLAYGROUND_URL",
+        default_value = "https://play.rust-lang.org"
+    )]
+    pub playground_url: String,
+    /// Build and run code samples on this machine instead of on the playground
+    #[structopt(long = "local")]
+    pub local: bool,
+    /// Rustup toolchain used to run code samples locally instead of the requested channel
+    #[structopt(long = "toolchain")]
+    pub toolchain: Option<String>,
+    /// Timeout in seconds when running code samples locally
+    #[structopt(long = "local-timeout", default_value = "30")]
+    pub local_timeout: u64,
+    /// Directory of vendored crates used to resolve dependencies when running locally
+    #[structopt(long = "vendor", parse(from_os_str))]
+    pub vendor: Option<PathBuf>,
+    /// Number of playground results kept to answer the same request again, 0 to disable
+    #[structopt(long = "result-cache-size", default_value = "1000")]
+    pub result_cache_size: usize,
+    /// Code samples built or run at the same time, on the playground or locally
+    #[structopt(long = "max-in-flight", default_value = "8")]
+    pub max_in_flight: usize,
+    /// Playground requests started per second
+    #[structopt(long = "playground-rate", default_value = "4")]
+    pub playground_rate: u32,
+    /// Retries of a playground request answered with `429 Too Many Requests` or a server error
+    #[structopt(long = "max-retries", default_value = "3")]
+    pub max_retries: u32,
+    /// SQLite database where the results of every run are recorded
+    #[structopt(long = "store", parse(from_os_str))]
+    pub store: Option<PathBuf>,
+    /// Directory where answers from Github are kept across restarts, in memory if not set
+    #[structopt(long = "github-cache", parse(from_os_str))]
+    pub github_cache: Option<PathBuf>,
+    /// Number of answers from Github after which the least recently used are dropped
+    #[structopt(long = "github-cache-entries", default_value = "10000")]
+    pub github_cache_entries: usize,
+    /// Size in megabytes after which the least recently used answers from Github are dropped
+    #[structopt(long = "github-cache-size", default_value = "64")]
+    pub github_cache_size: usize,
+    /// Age in hours after which answers from Github are dropped
+    #[structopt(long = "github-cache-age", default_value = "168")]
+    pub github_cache_age: u64,
+}
+
+impl Settings {
+    /// Set the backend running code samples, the result cache, the scheduler and the store.
+    /// The Github cache is set on its own, as a directory that cannot be opened is not an error
+    /// for every binary.
+    pub fn apply(&self) -> Result<(), failure::Error> {
+        playground::set_playground_url(&self.playground_url);
+        if self.local {
+            local::set_workers(self.max_in_flight);
+            playground::set_backend(playground::Local {
+                toolchain: self.toolchain.clone(),
+                timeout: Duration::new(self.local_timeout, 0),
+                vendor: self.vendor.clone(),
+                ..Default::default()
+            });
+        }
+        playground::cache::set_capacity(self.result_cache_size);
+        scheduler::set_limits(scheduler::Limits {
+            max_in_flight: self.max_in_flight.max(1),
+            min_interval: Duration::new(1, 0) / self.playground_rate.max(1),
+            max_retries: self.max_retries,
+            ..Default::default()
+        });
+        if let Some(ref path) = self.store {
+            store::set_store(path).map_err(|err| {
+                failure::err_msg(format!("could not open {}: {}", path.display(), err))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Keep answers from Github in the directory if one is set, in memory otherwise
+    pub fn set_github_cache(&self) -> Result<(), failure::Error> {
+        match self.github_cache {
+            Some(ref directory) => {
+                let disk =
+                    cache::Disk::open(directory, self.github_cache_bounds()).map_err(|err| {
+                        failure::err_msg(format!("could not open {}: {}", directory.display(), err))
+                    })?;
+                cache::set_cache(disk);
+            }
+            None => cache::set_cache(cache::Memory::new(self.github_cache_bounds())),
+        }
+        Ok(())
+    }
+
+    pub fn github_cache_bounds(&self) -> cache::Bounds {
+        cache::Bounds {
+            max_entries: self.github_cache_entries,
+            max_bytes: self.github_cache_size * 1_048_576,
+            max_age: Duration::new(self.github_cache_age * 3600, 0),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use actix_web::actix::{Actor, Addr, Handler, Message, SyncArbiter, SyncContext};
+use chrono::{DateTime, TimeZone, Utc};
+use failure;
+use hex;
+use rusqlite::{types::ToSql, Connection};
+use sha1::{Digest, Sha1};
+
+use playground::{Channel, SourceFile};
+
+/// One run of a code sample, on one channel
+#[derive(Serialize, Debug, Clone)]
+pub struct Run {
+    pub action: String,
+    pub channel: Channel,
+    /// Version of the compiler, if it could be known
+    pub version: Option<String>,
+    pub success: bool,
+    pub output: String,
+    pub verdict: Option<String>,
+    pub ts: DateTime<Utc>,
+    /// Whether the run behaved differently from the previous run of the same action on the same
+    /// channel
+    pub changed: bool,
+}
+
+/// Every recorded run of a code sample found at a place
+#[derive(Serialize, Debug, Clone)]
+pub struct SampleHistory {
+    pub sample: String,
+    pub from: String,
+    pub code: String,
+    pub runs: Vec<Run>,
+}
+
+/// SQLite database of the results of every run, queried through messages on a thread of its
+/// own so that the event loop is not blocked
+pub struct Store {
+    connection: Connection,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> Result<Store, failure::Error> {
+        Store::create_tables(Connection::open(path)?)
+    }
+
+    fn create_tables(connection: Connection) -> Result<Store, failure::Error> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS samples (
+                hash TEXT PRIMARY KEY,
+                code TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS runs (
+                id INTEGER PRIMARY KEY,
+                sample TEXT NOT NULL REFERENCES samples (hash),
+                source TEXT NOT NULL,
+                action TEXT NOT NULL,
+                channel TEXT NOT NULL,
+                version TEXT,
+                success INTEGER NOT NULL,
+                output TEXT NOT NULL,
+                verdict TEXT,
+                ts INTEGER NOT NULL
+            );
+            DROP INDEX IF EXISTS runs_by_source;
+            CREATE INDEX IF NOT EXISTS runs_by_source_nocase
+                ON runs (source COLLATE NOCASE, sample, ts);",
+        )?;
+        Ok(Store { connection })
+    }
+
+    /// Record runs of a code sample found at `from`
+    pub fn record(
+        &mut self,
+        from: &str,
+        code: &str,
+        files: &[SourceFile],
+        runs: &[Run],
+    ) -> Result<(), failure::Error> {
+        let transaction = self.connection.transaction()?;
+        let hash = sample_hash(code, files);
+        transaction.execute(
+            "INSERT OR IGNORE INTO samples (hash, code) VALUES (?1, ?2)",
+            &[&hash as &ToSql, &code],
+        )?;
+        for run in runs {
+            transaction.execute(
+                "INSERT INTO runs (sample, source, action, channel, version, success, output,
+                    verdict, ts)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                &[
+                    &hash as &ToSql,
+                    &from,
+                    &run.action,
+                    &run.channel.name(),
+                    &run.version,
+                    &run.success,
+                    &run.output,
+                    &run.verdict,
+                    &run.ts.timestamp(),
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Runs of the samples found at `from`, or in its comments when it is the URL of an issue.
+    /// Github URLs are compared without case, like Github compares owners and repositories.
+    pub fn history(&self, from: &str) -> Result<Vec<SampleHistory>, failure::Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT runs.sample, runs.source, samples.code, runs.action, runs.channel,
+                runs.version, runs.success, runs.output, runs.verdict, runs.ts
+            FROM runs JOIN samples ON samples.hash = runs.sample
+            WHERE runs.source = ?1 COLLATE NOCASE
+                OR substr(runs.source, 1, length(?1) + 1) = ?1 || '#' COLLATE NOCASE
+            ORDER BY runs.source, runs.sample, runs.ts, runs.id",
+        )?;
+        let rows = statement.query_map(&[&from as &ToSql], |row| {
+            let channel: String = row.get(4);
+            let ts: i64 = row.get(9);
+            (
+                row.get::<_, String>(0),
+                row.get::<_, String>(1),
+                row.get::<_, String>(2),
+                Run {
+                    action: row.get(3),
+                    channel: channel.parse().unwrap_or_default(),
+                    version: row.get(5),
+                    success: row.get(6),
+                    output: row.get(7),
+                    verdict: row.get(8),
+                    ts: Utc.timestamp(ts, 0),
+                    changed: false,
+                },
+            )
+        })?;
+        let mut histories: Vec<SampleHistory> = vec![];
+        for row in rows {
+            let (sample, from, code, mut run) = row?;
+            let is_same_sample = histories
+                .last()
+                .map(|history| history.sample == sample && history.from == from)
+                .unwrap_or(false);
+            if !is_same_sample {
+                histories.push(SampleHistory {
+                    sample,
+                    from,
+                    code,
+                    runs: vec![],
+                });
+            }
+            let history = histories.last_mut().unwrap();
+            run.changed = history
+                .runs
+                .iter()
+                .rev()
+                .find(|previous| previous.action == run.action && previous.channel == run.channel)
+                .map(|previous| {
+                    previous.success != run.success
+                        || previous.output != run.output
+                        || previous.verdict != run.verdict
+                }).unwrap_or(false);
+            history.runs.push(run);
+        }
+        Ok(histories)
+    }
+}
+
+/// Identifies a code sample with its other files
+pub fn sample_hash(code: &str, files: &[SourceFile]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input(code.as_bytes());
+    for file in files {
+        hasher.input(b"\0");
+        hasher.input(file.name.as_bytes());
+        hasher.input(b"\0");
+        hasher.input(file.content.as_bytes());
+    }
+    hex::encode(hasher.result())
+}
+
+impl Actor for Store {
+    type Context = SyncContext<Self>;
+}
+
+/// Record runs of a code sample found at `from`
+pub struct Record {
+    pub from: String,
+    pub code: String,
+    pub files: Vec<SourceFile>,
+    pub runs: Vec<Run>,
+}
+
+impl Message for Record {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<Record> for Store {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, record: Record, _: &mut SyncContext<Self>) -> Self::Result {
+        self.record(&record.from, &record.code, &record.files, &record.runs)
+    }
+}
+
+/// Runs of the samples found at `from`
+pub struct History {
+    pub from: String,
+}
+
+impl Message for History {
+    type Result = Result<Vec<SampleHistory>, failure::Error>;
+}
+
+impl Handler<History> for Store {
+    type Result = Result<Vec<SampleHistory>, failure::Error>;
+
+    fn handle(&mut self, history: History, _: &mut SyncContext<Self>) -> Self::Result {
+        self.history(&history.from)
+    }
+}
+
+lazy_static! {
+    static ref STORE_PATH: RwLock<Option<PathBuf>> = { RwLock::new(None) };
+    static ref STORE: Mutex<Option<Addr<Store>>> = { Mutex::new(None) };
+}
+
+/// Record results in the database at `path` from now on, they are not kept by default. It is
+/// opened now to report errors, and again by the thread running the queries once the actix
+/// system is running.
+pub fn set_store(path: &Path) -> Result<(), failure::Error> {
+    Store::open(path)?;
+    *STORE_PATH.write().unwrap() = Some(path.to_path_buf());
+    Ok(())
+}
+
+pub fn store() -> Option<Addr<Store>> {
+    let path = STORE_PATH.read().unwrap().clone()?;
+    let mut store = STORE.lock().unwrap();
+    let store = store.get_or_insert_with(|| {
+        SyncArbiter::start(1, move || {
+            Store::open(&path).expect("could not open the store again")
+        })
+    });
+    Some(store.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(channel: Channel, success: bool, output: &str, ts: i64) -> Run {
+        Run {
+            action: "run".to_string(),
+            channel,
+            version: None,
+            success,
+            output: output.to_string(),
+            verdict: None,
+            ts: Utc.timestamp(ts, 0),
+            changed: false,
+        }
+    }
+
+    #[test]
+    fn runs_are_read_back_with_their_changes() {
+        let mut store = Store::create_tables(Connection::open_in_memory().unwrap()).unwrap();
+        let issue = "https://github.com/Owner/Repo/issues/1";
+        let comment = "https://github.com/Owner/Repo/issues/1#issuecomment-2";
+        store
+            .record(
+                comment,
+                "fn main() {}",
+                &[],
+                &[
+                    run(Channel::Stable, true, "", 1),
+                    run(Channel::Nightly, true, "", 1),
+                ],
+            ).unwrap();
+        store
+            .record(
+                comment,
+                "fn main() {}",
+                &[],
+                &[
+                    run(Channel::Stable, true, "", 2),
+                    run(Channel::Nightly, false, "error", 2),
+                ],
+            ).unwrap();
+        store
+            .record(
+                issue,
+                "fn main() {}",
+                &[],
+                &[run(Channel::Stable, true, "", 3)],
+            ).unwrap();
+        // another issue of the same repository
+        store
+            .record(
+                "https://github.com/Owner/Repo/issues/10",
+                "fn main() {}",
+                &[],
+                &[run(Channel::Stable, true, "", 3)],
+            ).unwrap();
+
+        let histories = store
+            .history("https://github.com/owner/repo/issues/1")
+            .unwrap();
+        assert_eq!(histories.len(), 2);
+        assert_eq!(histories[0].from, issue);
+        assert_eq!(histories[0].runs.len(), 1);
+        assert_eq!(histories[1].from, comment);
+        assert_eq!(histories[1].code, "fn main() {}");
+        let changes = histories[1]
+            .runs
+            .iter()
+            .map(|run| (run.channel.name(), run.ts.timestamp(), run.changed))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                ("stable", 1, false),
+                ("nightly", 1, false),
+                ("stable", 2, false),
+                ("nightly", 2, true),
+            ]
+        );
+        assert!(store
+            .history("https://github.com/owner/repo/issues/2")
+            .unwrap()
+            .is_empty());
+    }
+}
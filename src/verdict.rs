@@ -0,0 +1,221 @@
+use failure;
+use futures::future::Future;
+
+use markdown::Code;
+use playground::{self, Options, Response};
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    /// The sample still behaves like the reporter described
+    Reproduces,
+    /// The sample behaves neither like expected nor like reported
+    Differs,
+    /// The sample now gives the expected output
+    Fixed,
+}
+impl Verdict {
+    pub fn name(self) -> &'static str {
+        match self {
+            Verdict::Reproduces => "reproduces",
+            Verdict::Differs => "differs",
+            Verdict::Fixed => "fixed",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VerdictResult {
+    pub verdict: Verdict,
+    pub success: bool,
+    pub output: String,
+    /// Line diff from the expected output to the actual output, empty when fixed
+    pub diff: String,
+}
+
+/// Run a sample and compare its output to the expected output block that follows it, `None` if
+/// there is no such block. Fence attributes are honored, a `no_run` or `compile_fail` sample
+/// being only built.
+pub fn ask_verdict(
+    code: &Code,
+    options: Options,
+) -> Option<impl Future<Item = VerdictResult, Error = failure::Error>> {
+    let expected = code.expected.clone()?;
+    let reported = code.reported.clone();
+    let attributes = code.attributes;
+    let action = attributes.action(playground::Action::Run)?;
+    Some(
+        playground::ask_sample(code, action, options).map(move |response| {
+            let success = attributes.is_success(action, &response);
+            judge(
+                &expected,
+                reported.as_ref().map(|r| r.as_str()),
+                success,
+                response,
+            )
+        }),
+    )
+}
+
+/// `success` is whether the sample did what its fence attributes say it should
+fn judge(
+    expected: &str,
+    reported: Option<&str>,
+    success: bool,
+    response: Response,
+) -> VerdictResult {
+    let actual = if response.success || response.stderr.is_empty() {
+        &response.stdout
+    } else {
+        &response.stderr
+    };
+    let matches = |block: &str| {
+        let block = normalize(block);
+        [&response.stdout, &response.stderr]
+            .iter()
+            .any(|output| contains_lines(&normalize(output), &block))
+    };
+    let verdict = if matches(expected) {
+        Verdict::Fixed
+    } else {
+        match reported {
+            Some(reported) if !matches(reported) => Verdict::Differs,
+            _ => Verdict::Reproduces,
+        }
+    };
+    VerdictResult {
+        verdict,
+        success,
+        diff: if verdict == Verdict::Fixed {
+            String::new()
+        } else {
+            diff(&normalize(expected), &normalize(actual))
+        },
+        output: actual.to_string(),
+    }
+}
+
+/// Lines of an output block without trailing whitespace, prompts like `$ cargo run`, or cargo
+/// progress lines
+fn normalize(output: &str) -> Vec<String> {
+    let mut lines = output
+        .lines()
+        .map(|line| line.trim_right())
+        .filter(|line| !line.starts_with("$ "))
+        .filter(|line| {
+            let line = line.trim_left();
+            !(line.starts_with("Compiling ")
+                || line.starts_with("Finished ")
+                || line.starts_with("Running "))
+        }).map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    while lines.last().map(|line| line.is_empty()) == Some(true) {
+        lines.pop();
+    }
+    let leading = lines.iter().take_while(|line| line.is_empty()).count();
+    lines.split_off(leading)
+}
+
+/// Whether `block` appears as consecutive lines of `output`
+fn contains_lines(output: &[String], block: &[String]) -> bool {
+    if block.is_empty() {
+        return output.is_empty();
+    }
+    output.windows(block.len()).any(|window| window == block)
+}
+
+/// Lines of each output compared by `diff`, which needs memory for the product of their numbers
+const MAX_DIFF_LINES: usize = 1000;
+
+/// Line diff based on the longest common subsequence, with `-` for lines only expected and `+`
+/// for lines only in the actual output
+fn diff(expected: &[String], actual: &[String]) -> String {
+    let truncated = expected.len() > MAX_DIFF_LINES || actual.len() > MAX_DIFF_LINES;
+    let expected = &expected[..expected.len().min(MAX_DIFF_LINES)];
+    let actual = &actual[..actual.len().min(MAX_DIFF_LINES)];
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        let in_both = i < expected.len() && j < actual.len() && expected[i] == actual[j];
+        let only_actual =
+            i == expected.len() || (j < actual.len() && common[i][j + 1] >= common[i + 1][j]);
+        if in_both {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if only_actual {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        }
+    }
+    if truncated {
+        lines.push(format!("[diff of the first {} lines]", MAX_DIFF_LINES));
+    }
+    lines
+        .into_iter()
+        .map(|line| format!("{}\n", line))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_marks_lines() {
+        assert_eq!(
+            diff(&lines("a\nb\nc"), &lines("a\nc\nd")),
+            "  a\n- b\n  c\n+ d\n"
+        );
+    }
+
+    #[test]
+    fn diff_of_long_outputs_is_truncated() {
+        let expected = (0..100_000).map(|i| i.to_string()).collect::<Vec<_>>();
+        let actual = (0..100_000)
+            .map(|i| (i + 1).to_string())
+            .collect::<Vec<_>>();
+        let diff = diff(&expected, &actual);
+        assert!(diff.starts_with("- 0\n  1\n"));
+        assert!(diff.ends_with("\n[diff of the first 1000 lines]\n"));
+        assert_eq!(diff.lines().count(), MAX_DIFF_LINES + 2);
+    }
+
+    #[test]
+    fn panic_of_a_should_panic_sample_is_a_success() {
+        let response = Response {
+            success: false,
+            stdout: String::new(),
+            stderr: "thread 'main' panicked at 'explicit panic', src/main.rs:2:5\n".to_string(),
+            code: None,
+            killed: false,
+        };
+        let verdict = judge(
+            "thread 'main' panicked at 'explicit panic', src/main.rs:2:5",
+            None,
+            true,
+            response,
+        );
+        assert_eq!(verdict.verdict, Verdict::Fixed);
+        assert!(verdict.success);
+        assert!(verdict.output.contains("panicked at"));
+    }
+}